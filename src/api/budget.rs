@@ -0,0 +1,117 @@
+//! Soft limit on the size of a single response, estimated from the converted entries,
+//! so that a page of huge binary values can't exhaust memory within the row `limit`.
+
+use super::errors::AppError;
+use super::{DataEntry, DataEntryFragment, DataEntryType, DataEntryValueFragment};
+
+// braces, field names and separators of an entry
+const ENTRY_OVERHEAD: usize = 64;
+const FRAGMENT_OVERHEAD: usize = 32;
+// an i64 or a height, in decimal
+const NUMBER_LEN: usize = 20;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResponseBudget {
+    max_bytes: Option<usize>,
+}
+
+impl ResponseBudget {
+    pub fn new(max_bytes: Option<usize>) -> Self {
+        Self { max_bytes }
+    }
+
+    /// Keeps the leading entries fitting into the budget,
+    /// returns them with a flag telling whether any entries were left out.
+    /// Fails when not even the first entry fits.
+    pub fn take(&self, entries: Vec<DataEntry>) -> Result<(Vec<DataEntry>, bool), AppError> {
+        let max_bytes = match self.max_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return Ok((entries, false)),
+        };
+
+        let total = entries.len();
+        let mut bytes = 0;
+        let kept = entries
+            .into_iter()
+            .take_while(|entry| {
+                bytes += entry.estimated_size();
+                bytes <= max_bytes
+            })
+            .collect::<Vec<_>>();
+
+        if kept.is_empty() && total > 0 {
+            return Err(AppError::ResponseTooLarge(max_bytes));
+        }
+
+        let partial = kept.len() < total;
+        Ok((kept, partial))
+    }
+
+    /// Checks that all entries fit into the budget, for responses which can't be paginated.
+    pub fn check(&self, entries: &[Option<DataEntry>]) -> Result<(), AppError> {
+        match self.max_bytes {
+            Some(max_bytes) => {
+                let bytes = entries
+                    .iter()
+                    .flatten()
+                    .map(DataEntry::estimated_size)
+                    .sum::<usize>();
+                if bytes > max_bytes {
+                    Err(AppError::ResponseTooLarge(max_bytes))
+                } else {
+                    Ok(())
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl DataEntry {
+    fn estimated_size(&self) -> usize {
+        let value = match &self.value {
+            // serialized as an array of numbers, up to 4 chars per byte
+            Some(DataEntryType::BinaryVal(v)) => v.len() * 4,
+            Some(DataEntryType::StringVal(v)) => v.len(),
+            Some(DataEntryType::BoolVal(_)) | Some(DataEntryType::IntVal(_)) | None => NUMBER_LEN,
+        };
+        let fragments = self
+            .fragments
+            .as_ref()
+            .map(|fragments| {
+                let key = fragments.key.iter().map(|f| match f {
+                    DataEntryFragment::String { value } => value.len(),
+                    DataEntryFragment::Integer { .. } => NUMBER_LEN,
+                });
+                let value = fragments.value.iter().map(|f| match f {
+                    DataEntryValueFragment::String { value } => value.len(),
+                    DataEntryValueFragment::Integer { .. } => NUMBER_LEN,
+                });
+                key.chain(value).map(|len| len + FRAGMENT_OVERHEAD).sum()
+            })
+            .unwrap_or(0);
+
+        let flat_fragments = self
+            .flat_fragments
+            .as_ref()
+            .map(|flat_fragments| {
+                flat_fragments
+                    .0
+                    .iter()
+                    .map(|(_, value)| match value {
+                        serde_json::Value::String(value) => value.len() + FRAGMENT_OVERHEAD,
+                        _ => NUMBER_LEN + FRAGMENT_OVERHEAD,
+                    })
+                    .sum()
+            })
+            .unwrap_or(0);
+
+        ENTRY_OVERHEAD
+            + self.address.as_ref().map_or(0, String::len)
+            + self.key.as_ref().map_or(0, String::len)
+            + NUMBER_LEN
+            + value
+            + fragments
+            + flat_fragments
+    }
+}
@@ -0,0 +1,219 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use super::fields::{Fields, FIELDS_LIMIT};
+use super::parsing::{
+    FragmentType, Operation, ToType, ValueType, BINARY_ENCODINGS, BYTE_ARRAY_ENCODING,
+    FILTER_KINDS, FRAGMENT_POSITION_MAX, KEY_PREFIXES_MAX, LIMIT_MAX, SORT_KINDS,
+    VALUE_JSON_DEPTH_MAX,
+};
+use super::{ResponseFormat, KEYS_LIMIT};
+use crate::config::{Config, RouteGroup};
+
+/// Filters, operations, limits and features supported by this server.
+/// Built from the same constants the validators use and from the loaded config,
+/// so it can't drift from them.
+#[derive(Clone, Debug, Serialize)]
+pub struct Capabilities {
+    filters: Vec<&'static str>,
+    value_operations: BTreeMap<String, Vec<String>>,
+    fragment_operations: BTreeMap<String, Vec<String>>,
+    sort: Vec<&'static str>,
+    fields: Vec<&'static str>,
+    max_fragment_position: u64,
+    limits: Limits,
+    features: Features,
+    config: ActiveConfig,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Limits {
+    search: u64,
+    mget_keys: u16,
+    fields: usize,
+    value_json_depth: usize,
+    key_prefixes: usize,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Features {
+    historical: bool,
+    coalescing: bool,
+    admin: bool,
+    formats: Vec<&'static str>,
+}
+
+/// Settings of this deployment, taken from its config.
+#[derive(Clone, Debug, Serialize)]
+struct ActiveConfig {
+    disabled_routes: Vec<RouteGroup>,
+    max_response_bytes: Option<usize>,
+    pretty_responses: bool,
+    binary_encoding: BinaryEncoding,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct BinaryEncoding {
+    response: &'static str,
+    request: Vec<&'static str>,
+}
+
+impl Capabilities {
+    pub fn new(config: &Config) -> Self {
+        let enabled = |group| !config.disabled_routes.contains(&group);
+        // historical reads and the coalesced latest-state lookups are served by mget routes
+        let mget_enabled = enabled(RouteGroup::Mget);
+        Self {
+            filters: FILTER_KINDS.to_vec(),
+            value_operations: ValueType::ALL
+                .iter()
+                .map(|t| (t.to_type(), operation_names(t.operations())))
+                .collect(),
+            fragment_operations: FragmentType::ALL
+                .iter()
+                .map(|t| (t.to_type(), operation_names(t.operations())))
+                .collect(),
+            sort: SORT_KINDS.to_vec(),
+            fields: Fields::default().names(),
+            max_fragment_position: FRAGMENT_POSITION_MAX,
+            limits: Limits {
+                search: LIMIT_MAX,
+                mget_keys: KEYS_LIMIT,
+                fields: FIELDS_LIMIT,
+                value_json_depth: VALUE_JSON_DEPTH_MAX,
+                key_prefixes: KEY_PREFIXES_MAX,
+            },
+            features: Features {
+                historical: mget_enabled,
+                coalescing: mget_enabled,
+                admin: config.admin_token.is_some() && enabled(RouteGroup::Admin),
+                formats: ResponseFormat::ALL
+                    .iter()
+                    .map(ResponseFormat::name)
+                    .collect(),
+            },
+            config: ActiveConfig {
+                disabled_routes: config.disabled_routes.clone(),
+                max_response_bytes: config.max_response_bytes,
+                pretty_responses: config.pretty_responses,
+                binary_encoding: BinaryEncoding {
+                    response: BYTE_ARRAY_ENCODING,
+                    request: BINARY_ENCODINGS.to_vec(),
+                },
+            },
+        }
+    }
+}
+
+fn operation_names(operations: Vec<Operation>) -> Vec<String> {
+    operations.iter().map(|op| op.to_type()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{PostgresConfig, TracingConfig};
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn config() -> Config {
+        Config {
+            port: 8080,
+            metrics_port: 9090,
+            admin_token: None,
+            db_breaker_failures: 5,
+            db_breaker_cooldown: Duration::from_secs(10),
+            max_response_bytes: None,
+            rate_limit_per_sec: None,
+            rate_limit_burst: None,
+            disabled_routes: vec![],
+            value_md5_min_len: 256,
+            pretty_responses: false,
+            postgres: PostgresConfig {
+                host: "localhost".into(),
+                port: 5432,
+                database: "state".into(),
+                user: "state".into(),
+                password: "state".into(),
+                pool_size: 4,
+                schema: "public".into(),
+                instance: None,
+                max_lifetime: Duration::from_secs(30 * 60),
+                idle_timeout: Duration::from_secs(5 * 60),
+                connection_timeout: Duration::from_secs(5),
+            },
+            tracing: TracingConfig {
+                service_name_prefix: None,
+                jaeger_agent_endpoint: None,
+                sample_rate: None,
+            },
+        }
+    }
+
+    fn capabilities(config: &Config) -> serde_json::Value {
+        serde_json::to_value(Capabilities::new(config)).unwrap()
+    }
+
+    #[test]
+    fn limits_match_validators() {
+        let caps = capabilities(&config());
+
+        assert_eq!(
+            caps["limits"],
+            json!({
+                "search": LIMIT_MAX,
+                "mget_keys": KEYS_LIMIT,
+                "fields": FIELDS_LIMIT,
+                "value_json_depth": VALUE_JSON_DEPTH_MAX,
+                "key_prefixes": KEY_PREFIXES_MAX,
+            })
+        );
+        assert_eq!(caps["max_fragment_position"], FRAGMENT_POSITION_MAX);
+        assert_eq!(caps["filters"], json!(FILTER_KINDS));
+        assert_eq!(caps["sort"], json!(SORT_KINDS));
+        assert_eq!(
+            caps["config"]["binary_encoding"]["request"],
+            json!(BINARY_ENCODINGS)
+        );
+    }
+
+    #[test]
+    fn features_follow_enabled_routes() {
+        let caps = capabilities(&Config {
+            admin_token: Some("secret".into()),
+            ..config()
+        });
+        assert_eq!(caps["features"]["historical"], true);
+        assert_eq!(caps["features"]["coalescing"], true);
+        assert_eq!(caps["features"]["admin"], true);
+
+        let caps = capabilities(&Config {
+            admin_token: Some("secret".into()),
+            disabled_routes: vec![RouteGroup::Mget, RouteGroup::Admin],
+            ..config()
+        });
+        assert_eq!(caps["features"]["historical"], false);
+        assert_eq!(caps["features"]["coalescing"], false);
+        assert_eq!(caps["features"]["admin"], false);
+    }
+
+    #[test]
+    fn reported_config_matches_loaded_one() {
+        let config = Config {
+            disabled_routes: vec![RouteGroup::Changes, RouteGroup::Aggregate],
+            max_response_bytes: Some(1 << 20),
+            pretty_responses: true,
+            ..config()
+        };
+
+        let caps = capabilities(&config);
+
+        assert_eq!(
+            caps["config"]["disabled_routes"],
+            json!(["changes", "aggregate"])
+        );
+        assert_eq!(caps["config"]["max_response_bytes"], 1 << 20);
+        assert_eq!(caps["config"]["pretty_responses"], true);
+        assert_eq!(caps["features"]["admin"], false);
+    }
+}
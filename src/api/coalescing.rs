@@ -0,0 +1,208 @@
+use futures::future::{BoxFuture, FutureExt, Shared};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use super::errors::AppError;
+use super::fields::Fields;
+use super::parsing::{Entry, MgetEntries};
+use crate::data_entries::{DataEntry, Repo};
+
+type LookupResult = Result<Vec<DataEntry>, AppError>;
+type SharedLookup = Shared<BoxFuture<'static, LookupResult>>;
+
+/// Single-flight for latest-state lookups: concurrent requests for the same
+/// (address, key) share one in-flight db query and get the same result.
+#[derive(Clone, Default)]
+pub struct Coalescer {
+    in_flight: Arc<Mutex<InFlight>>,
+}
+
+#[derive(Default)]
+struct InFlight {
+    // every lookup gets its own id, so that a finished one removes only itself
+    lookups: HashMap<(String, String), (u64, SharedLookup)>,
+    next_id: u64,
+}
+
+impl Coalescer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn latest_data_entries(
+        &self,
+        repo: Repo,
+        address: String,
+        key: String,
+    ) -> LookupResult {
+        let lookup_key = (address, key);
+        let mget_entries = MgetEntries {
+            address_key_pairs: vec![Entry {
+                address: lookup_key.0.clone(),
+                key: lookup_key.1.clone(),
+            }],
+            filter: None,
+        };
+
+        self.coalesce(lookup_key, || async move {
+            repo.mget_data_entries(mget_entries, &[], &Fields::default())
+                .await
+                .map_err(AppError::from)
+        })
+        .await
+    }
+
+    /// Forgets all in-flight lookups, so the next request for any key queries the db
+    /// instead of joining a lookup started before the flush.
+    /// Results are never kept after a lookup completes, so there is nothing else to drop.
+    pub fn clear(&self) {
+        self.in_flight.lock().unwrap().lookups.clear();
+    }
+
+    /// Joins the in-flight lookup of the key, or starts one with `fetch`.
+    async fn coalesce<F, Fut>(&self, lookup_key: (String, String), fetch: F) -> LookupResult
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = LookupResult> + Send + 'static,
+    {
+        let lookup = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.lookups.get(&lookup_key) {
+                Some((_, lookup)) => lookup.clone(),
+                None => {
+                    let id = in_flight.next_id;
+                    in_flight.next_id += 1;
+                    let lookup = self
+                        .lookup(lookup_key.clone(), id, fetch())
+                        .boxed()
+                        .shared();
+                    in_flight.lookups.insert(lookup_key, (id, lookup.clone()));
+                    lookup
+                }
+            }
+        };
+
+        lookup.await
+    }
+
+    fn lookup(
+        &self,
+        lookup_key: (String, String),
+        id: u64,
+        fetch: impl Future<Output = LookupResult> + Send + 'static,
+    ) -> impl Future<Output = LookupResult> + Send + 'static {
+        let in_flight = self.in_flight.clone();
+        async move {
+            let res = fetch.await;
+
+            // the next request for this key after completion must hit the db again,
+            // unless the key was flushed and another lookup is in flight already
+            let mut in_flight = in_flight.lock().unwrap();
+            if matches!(in_flight.lookups.get(&lookup_key), Some((current, _)) if *current == id) {
+                in_flight.lookups.remove(&lookup_key);
+            }
+
+            res
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use futures::poll;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::{oneshot, Semaphore};
+
+    fn lookup_key() -> (String, String) {
+        ("3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP".into(), "price".into())
+    }
+
+    #[tokio::test]
+    async fn concurrent_lookups_share_one_fetch() {
+        let coalescer = Coalescer::new();
+        let fetches = Arc::new(AtomicUsize::new(0));
+        // keeps the first fetch in flight until every request has joined it
+        let gate = Arc::new(Semaphore::new(0));
+
+        let mut lookups = (0..10)
+            .map(|_| {
+                let fetches = fetches.clone();
+                let gate = gate.clone();
+                Box::pin(coalescer.coalesce(lookup_key(), move || async move {
+                    fetches.fetch_add(1, Ordering::SeqCst);
+                    let _permit = gate.acquire().await.unwrap();
+                    Ok(vec![])
+                }))
+            })
+            .collect::<Vec<_>>();
+        for lookup in lookups.iter_mut() {
+            assert!(poll!(lookup).is_pending());
+        }
+
+        gate.add_permits(1);
+        let results = join_all(lookups).await;
+
+        assert_eq!(results.len(), 10);
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert!(coalescer.in_flight.lock().unwrap().lookups.is_empty());
+    }
+
+    #[tokio::test]
+    async fn lookup_after_flush_fetches_again() {
+        let coalescer = Coalescer::new();
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let gate = Arc::new(Semaphore::new(0));
+
+        let lookup = || {
+            let fetches = fetches.clone();
+            let gate = gate.clone();
+            Box::pin(coalescer.coalesce(lookup_key(), move || async move {
+                fetches.fetch_add(1, Ordering::SeqCst);
+                let _permit = gate.acquire().await.unwrap();
+                Ok(vec![])
+            }))
+        };
+
+        let mut before_flush = lookup();
+        assert!(poll!(&mut before_flush).is_pending());
+        coalescer.clear();
+        let mut after_flush = lookup();
+        assert!(poll!(&mut after_flush).is_pending());
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        gate.add_permits(2);
+        before_flush.await.unwrap();
+        after_flush.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn lookup_finished_after_flush_keeps_newer_one() {
+        let coalescer = Coalescer::new();
+        let (release, released) = oneshot::channel::<()>();
+        let (_hold, held) = oneshot::channel::<()>();
+
+        let mut stale = Box::pin(coalescer.coalesce(lookup_key(), || async move {
+            released.await.unwrap();
+            Ok(vec![])
+        }));
+        assert!(poll!(&mut stale).is_pending());
+
+        coalescer.clear();
+        let mut fresh = Box::pin(coalescer.coalesce(lookup_key(), || async move {
+            let _ = held.await;
+            Ok(vec![])
+        }));
+        assert!(poll!(&mut fresh).is_pending());
+
+        release.send(()).unwrap();
+        stale.await.unwrap();
+
+        let in_flight = coalescer.in_flight.lock().unwrap();
+        assert!(in_flight.lookups.contains_key(&lookup_key()));
+    }
+}
@@ -0,0 +1,146 @@
+use base64::encode;
+use std::convert::Infallible;
+use warp::http::header::{HeaderValue, CONTENT_TYPE};
+use warp::hyper::Body;
+use warp::reply::Response;
+
+use super::parsing::FRAGMENT_POSITION_MAX;
+use super::{DataEntriesResponse, DataEntry, DataEntryFragment, DataEntryType};
+
+// one column per fragment position a filter can refer to
+const FRAGMENTS_COUNT: usize = FRAGMENT_POSITION_MAX as usize + 1;
+
+pub fn is_csv_accepted(accept: &Option<String>) -> bool {
+    accept
+        .as_ref()
+        .map(|accept| accept.contains("text/csv") || accept.contains("application/csv"))
+        .unwrap_or(false)
+}
+
+impl DataEntriesResponse {
+    /// Writes entries as csv, one row per entry, with key fragments flattened into columns.
+    /// The entries are already loaded, only their rows are formatted as the body is sent.
+    pub fn into_csv_response(self) -> Response {
+        let header = std::iter::once(csv_header());
+        let rows = self.entries.into_iter().map(|entry| csv_row(&entry));
+        let lines = header.chain(rows).map(Ok::<_, Infallible>);
+
+        let mut res = Response::new(Body::wrap_stream(futures::stream::iter(lines)));
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("text/csv"));
+        res
+    }
+}
+
+fn csv_header() -> String {
+    let mut columns = vec![
+        "address".to_string(),
+        "key".to_string(),
+        "height".to_string(),
+        "value_type".to_string(),
+        "value".to_string(),
+    ];
+    columns.extend((0..FRAGMENTS_COUNT).map(|i| format!("fragment_{}", i)));
+    columns.join(",") + "\n"
+}
+
+fn csv_row(entry: &DataEntry) -> String {
+    let (value_type, value) = match &entry.value {
+        Some(DataEntryType::BinaryVal(v)) => ("binary", encode(v)),
+        Some(DataEntryType::BoolVal(v)) => ("bool", v.to_string()),
+        Some(DataEntryType::IntVal(v)) => ("integer", v.to_string()),
+        Some(DataEntryType::StringVal(v)) => ("string", v.clone()),
+        None => ("", "".to_string()),
+    };
+
+    let mut columns = vec![
+        entry.address.clone().unwrap_or_default(),
+        entry.key.clone().unwrap_or_default(),
+        entry
+            .height
+            .flatten()
+            .map(|h| h.to_string())
+            .unwrap_or_default(),
+        value_type.to_string(),
+        value,
+    ];
+    let key_fragments = entry.fragments.as_ref().map(|f| f.key.as_slice());
+    columns.extend(
+        (0..FRAGMENTS_COUNT).map(|i| match key_fragments.and_then(|f| f.get(i)) {
+            Some(DataEntryFragment::String { value }) => value.clone(),
+            Some(DataEntryFragment::Integer { value }) => value.to_string(),
+            None => "".to_string(),
+        }),
+    );
+
+    columns
+        .iter()
+        .map(|c| csv_escape(c))
+        .collect::<Vec<_>>()
+        .join(",")
+        + "\n"
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_entries;
+
+    #[test]
+    fn header_lists_fragment_columns() {
+        assert_eq!(
+            csv_header(),
+            "address,key,height,value_type,value,fragment_0,fragment_1,fragment_2,\
+fragment_3,fragment_4,fragment_5,fragment_6,fragment_7,fragment_8,fragment_9,fragment_10\n"
+        );
+    }
+
+    #[test]
+    fn row_flattens_entry() {
+        let entry = DataEntry::from(data_entries::DataEntry {
+            address: "3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP".into(),
+            key: "price_7".into(),
+            height: Some(2_500_000),
+            value_string: Some("1,5 \"waves\"".into()),
+            fragment_0_string: Some("price".into()),
+            fragment_1_integer: Some(7),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            csv_row(&entry),
+            "3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP,price_7,2500000,string,\"1,5 \"\"waves\"\"\",\
+price,7,,,,,,,,,\n"
+        );
+    }
+
+    #[test]
+    fn binary_values_are_base64() {
+        let entry = DataEntry::from(data_entries::DataEntry {
+            address: "3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP".into(),
+            key: "data".into(),
+            value_binary: Some(vec![1, 2, 3]),
+            ..Default::default()
+        });
+
+        assert!(
+            csv_row(&entry).starts_with("3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP,data,,binary,AQID,")
+        );
+    }
+
+    #[test]
+    fn escapes_only_special_fields() {
+        assert_eq!(csv_escape("plain"), "plain");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -3,9 +3,17 @@ use regex::Regex;
 use serde::Serialize;
 use std::collections::HashMap;
 use std::fmt;
-use warp::reject::Reject;
+use warp::{
+    http::StatusCode,
+    reject::Reject,
+    reply::{json, with_status, Reply, Response},
+};
 
 const VALIDATION_ERROR_TITLE: &str = "Validation Error";
+// pooled connections are held for a single query, so one frees up soon
+const POOL_RETRY_AFTER_SECS: u64 = 1;
+// raised by postgres for both json and jsonb casts
+const INVALID_JSON_MESSAGE: &str = "invalid input syntax for type json";
 const MISSING_FIELD_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"missing field `(\w+)`").unwrap());
 const INVALID_VALUE_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"invalid value: (\w+) `(.*)`, expected (\w+)").unwrap());
@@ -15,6 +23,18 @@ pub enum AppError {
     DbError(String),
     ValidationError(String, u32, Option<ErrorDetails>),
     DecodePathError(String),
+    Unauthorized,
+    // a single row lookup found nothing
+    NotFound,
+    IndexerBehind(i32),
+    // the db circuit is open, holds seconds until the next attempt
+    DbUnavailable(u64),
+    // the response would exceed the configured budget, holds the budget in bytes
+    ResponseTooLarge(usize),
+    // the request body is declared with a content type other than json
+    UnsupportedMediaType(String),
+    // the client exceeded its request rate, holds seconds until the next request is allowed
+    RateLimited(u64),
 }
 
 impl fmt::Display for AppError {
@@ -27,21 +47,82 @@ impl fmt::Display for AppError {
                 msg, code, details
             ),
             AppError::DecodePathError(msg) => write!(f, "DecodePathError: {}", msg),
+            AppError::Unauthorized => write!(f, "Unauthorized"),
+            AppError::NotFound => write!(f, "NotFound"),
+            AppError::DbUnavailable(retry_after) => {
+                write!(f, "DbUnavailable: retry after {}s", retry_after)
+            }
+            AppError::IndexerBehind(height) => {
+                write!(f, "IndexerBehind: last indexed height {}", height)
+            }
+            AppError::ResponseTooLarge(max_bytes) => {
+                write!(f, "ResponseTooLarge: exceeds {} bytes", max_bytes)
+            }
+            AppError::UnsupportedMediaType(content_type) => {
+                write!(f, "UnsupportedMediaType: {}", content_type)
+            }
+            AppError::RateLimited(retry_after) => {
+                write!(f, "RateLimited: retry after {}s", retry_after)
+            }
         }
     }
 }
 
 impl Reject for AppError {}
 
+impl From<crate::error::Error> for AppError {
+    fn from(err: crate::error::Error) -> Self {
+        match err {
+            // rounded up, so that clients never retry before the probe is allowed
+            crate::error::Error::CircuitOpen(retry_after) => {
+                AppError::DbUnavailable(retry_after.as_secs() + 1)
+            }
+            crate::error::Error::DbError(diesel::result::Error::NotFound) => AppError::NotFound,
+            // `value_json` filters cast the string values starting like json,
+            // a malformed one fails the whole query
+            crate::error::Error::DbError(diesel::result::Error::DatabaseError(_, info))
+                if info.message().starts_with(INVALID_JSON_MESSAGE) =>
+            {
+                AppError::new_validation_error(
+                    ValidationErrorCode::InvalidParamenterValue,
+                    ErrorDetails {
+                        parameter: "value_json".to_string(),
+                        reason: "a matched value is not valid json, narrow down the filter so that it only matches json values.".to_string(),
+                    },
+                )
+            }
+            // the pool only fails to hand out a connection after waiting for one in vain
+            crate::error::Error::ConnectionPoolError(_) => {
+                AppError::DbUnavailable(POOL_RETRY_AFTER_SECS)
+            }
+            err => AppError::DbError(err.to_string()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct ErrorDetails {
     pub parameter: String,
     pub reason: String,
 }
 
+/// 950 followed by the http status of the response,
+/// 10 is added to the status for a second error served with it.
+pub enum ErrorCode {
+    Unauthorized = 950401,
+    NotFound = 950404,
+    ResponseTooLarge = 950413,
+    UnsupportedMediaType = 950415,
+    RateLimited = 950429,
+    DbUnavailable = 950503,
+    // also served with 503, the code tells it apart from DbUnavailable
+    IndexerBehind = 950513,
+}
+
 pub enum ValidationErrorCode {
     MissingRequiredParameter = 950200,
     InvalidParamenterValue = 950201,
+    ConflictingParameters = 950202,
     UnknownError = 950299,
 }
 
@@ -55,6 +136,36 @@ impl AppError {
     }
 }
 
+#[derive(Serialize)]
+struct ErrorListResponse {
+    errors: Vec<ErrorResponse>,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    code: u32,
+    message: String,
+    details: Option<ErrorDetails>,
+}
+
+/// Builds an error response with the given status and a single error item,
+/// for the cases not covered by `wavesexchange_warp::error` helpers.
+pub fn error_response(
+    status: StatusCode,
+    code: u32,
+    message: &str,
+    details: Option<ErrorDetails>,
+) -> Response {
+    let body = ErrorListResponse {
+        errors: vec![ErrorResponse {
+            code,
+            message: message.to_owned(),
+            details,
+        }],
+    };
+    with_status(json(&body), status).into_response()
+}
+
 impl From<serde_path_to_error::Error<serde_json::Error>> for AppError {
     fn from(e: serde_path_to_error::Error<serde_json::Error>) -> Self {
         let path = e.path().to_string();
@@ -122,3 +233,25 @@ impl From<ErrorDetails> for HashMap<String, String> {
         hm
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+    #[test]
+    fn malformed_json_value_is_a_validation_error() {
+        let err = AppError::from(crate::error::Error::DbError(DieselError::DatabaseError(
+            DatabaseErrorKind::__Unknown,
+            Box::new("invalid input syntax for type json".to_string()),
+        )));
+
+        match err {
+            AppError::ValidationError(_, code, Some(details)) => {
+                assert_eq!(code, ValidationErrorCode::InvalidParamenterValue as u32);
+                assert_eq!(details.parameter, "value_json");
+            }
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+}
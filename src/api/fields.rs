@@ -0,0 +1,193 @@
+use super::{bool_from_hashmap, AppError, ErrorDetails, Rejection, ValidationErrorCode};
+use std::collections::HashMap;
+
+// every field may be requested once
+pub const FIELDS_LIMIT: usize = 6;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+    Address,
+    Key,
+    Height,
+    Value,
+    Fragments,
+    ValueSize,
+}
+
+impl Field {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Address => "address",
+            Field::Key => "key",
+            Field::Height => "height",
+            Field::Value => "value",
+            Field::Fragments => "fragments",
+            Field::ValueSize => "value_size",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "address" => Some(Field::Address),
+            "key" => Some(Field::Key),
+            "height" => Some(Field::Height),
+            "value" => Some(Field::Value),
+            "fragments" => Some(Field::Fragments),
+            "value_size" => Some(Field::ValueSize),
+            _ => None,
+        }
+    }
+}
+
+/// Entry fields requested via `?fields=address,key,height`.
+/// All of them but `fragments` and `value_size` by default,
+/// `?include_fragments=true` and `?include_value_size=true` add those.
+/// Csv and protobuf responses always get fragments, see `format_query_params`.
+#[derive(Clone, Debug)]
+pub struct Fields(Vec<Field>);
+
+impl Default for Fields {
+    fn default() -> Self {
+        Fields(vec![
+            Field::Address,
+            Field::Key,
+            Field::Height,
+            Field::Value,
+            Field::Fragments,
+            Field::ValueSize,
+        ])
+    }
+}
+
+impl Fields {
+    pub fn only(fields: &[Field]) -> Self {
+        Fields(fields.to_vec())
+    }
+
+    pub fn from_hashmap(m: &HashMap<String, String>) -> Result<Self, Rejection> {
+        let mut fields = Self::requested(m)?;
+
+        for (param, field) in &[
+            ("include_fragments", Field::Fragments),
+            ("include_value_size", Field::ValueSize),
+            // flattened into `frag_N` fields
+            ("flat", Field::Fragments),
+        ] {
+            if bool_from_hashmap(m, param)? && !fields.contains(field) {
+                fields.0.push(field.clone());
+            }
+        }
+
+        Ok(fields)
+    }
+
+    fn requested(m: &HashMap<String, String>) -> Result<Self, Rejection> {
+        let names = match m.get("fields") {
+            Some(names) => names,
+            None => {
+                let mut fields = Self::default();
+                fields
+                    .0
+                    .retain(|field| *field != Field::Fragments && *field != Field::ValueSize);
+                return Ok(fields);
+            }
+        };
+
+        let names = names.split(',').collect::<Vec<_>>();
+        if names.len() > FIELDS_LIMIT {
+            return Err(invalid_fields(format!(
+                "Too many fields. The maximum number of fields is {}.",
+                FIELDS_LIMIT
+            )));
+        }
+
+        let fields = names
+            .into_iter()
+            .map(|name| {
+                Field::from_name(name.trim()).ok_or_else(|| {
+                    invalid_fields(format!(
+                        "unknown field `{}`, expected one of: address, key, height, value, fragments, value_size.",
+                        name
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (i, field) in fields.iter().enumerate() {
+            if fields[..i].contains(field) {
+                return Err(invalid_fields(format!(
+                    "duplicate field `{}`.",
+                    field.name()
+                )));
+            }
+        }
+
+        Ok(Fields(fields))
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.0.iter().map(Field::name).collect()
+    }
+
+    pub fn contains(&self, field: &Field) -> bool {
+        self.0.contains(field)
+    }
+}
+
+fn invalid_fields(reason: String) -> Rejection {
+    let details = ErrorDetails {
+        parameter: "fields".to_string(),
+        reason,
+    };
+    warp::reject::custom::<AppError>(AppError::new_validation_error(
+        ValidationErrorCode::InvalidParamenterValue,
+        details,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(names: &str) -> Result<Fields, Rejection> {
+        let mut m = HashMap::new();
+        m.insert("fields".to_string(), names.to_string());
+        Fields::from_hashmap(&m)
+    }
+
+    fn reason(rejection: Rejection) -> String {
+        match rejection.find::<AppError>() {
+            Some(AppError::ValidationError(_, _, Some(details))) => details.reason.clone(),
+            err => panic!("unexpected rejection {:?}", err),
+        }
+    }
+
+    #[test]
+    fn parses_requested_fields() {
+        let fields = fields("address, key,value_size").unwrap();
+
+        assert_eq!(fields.names(), vec!["address", "key", "value_size"]);
+    }
+
+    #[test]
+    fn rejects_unknown_field() {
+        let reason = reason(fields("address,balance").unwrap_err());
+
+        assert!(reason.starts_with("unknown field `balance`"));
+    }
+
+    #[test]
+    fn rejects_duplicate_field() {
+        let reason = reason(fields("key,address,key").unwrap_err());
+
+        assert_eq!(reason, "duplicate field `key`.");
+    }
+
+    #[test]
+    fn rejects_over_limit_list() {
+        let names = ["key"; FIELDS_LIMIT + 1].join(",");
+        let reason = reason(fields(&names).unwrap_err());
+
+        assert!(reason.starts_with("Too many fields."));
+    }
+}
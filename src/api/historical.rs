@@ -88,11 +88,29 @@ impl HistoricalRequestParams {
                 Err(
                     warp::reject::custom::<AppError>(
                         AppError::new_validation_error(
-                            ValidationErrorCode::InvalidParamenterValue, details)
+                            ValidationErrorCode::ConflictingParameters, details)
                         )
                 )
         }
-            
+
+        // heights fit i32, larger values would wrap wherever they are narrowed
+        if let Some(height) = self.height {
+            if height < 0 || height > i32::MAX as i64 {
+                let details = ErrorDetails {
+                    parameter: "height".into(),
+                    reason: format!("height must be between 0 and {}", i32::MAX),
+                };
+
+                return
+                    Err(
+                        warp::reject::custom::<AppError>(
+                            AppError::new_validation_error(
+                                ValidationErrorCode::InvalidParamenterValue, details)
+                            )
+                    )
+            }
+        }
+
         Ok(())
     }
 }
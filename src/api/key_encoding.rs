@@ -0,0 +1,98 @@
+use super::{AppError, ErrorDetails, Rejection, ValidationErrorCode};
+use std::collections::HashMap;
+
+/// Encoding of the keys passed in the path or query, selected via `?key_encoding=base58`.
+/// Base58 lets integrations pass keys without url escaping.
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyEncoding {
+    Plain,
+    Base58,
+}
+
+impl Default for KeyEncoding {
+    fn default() -> Self {
+        KeyEncoding::Plain
+    }
+}
+
+impl KeyEncoding {
+    pub fn from_hashmap(m: &HashMap<String, String>) -> Result<Self, Rejection> {
+        match m.get("key_encoding").map(|s| s.as_str()) {
+            None => Ok(Self::default()),
+            Some("plain") => Ok(KeyEncoding::Plain),
+            Some("base58") => Ok(KeyEncoding::Base58),
+            Some(other) => Err(invalid_key_encoding(
+                "key_encoding",
+                format!(
+                    "unknown key encoding `{}`, expected one of: plain, base58.",
+                    other
+                ),
+            )),
+        }
+    }
+
+    pub fn decode(&self, key: String) -> Result<String, Rejection> {
+        match self {
+            KeyEncoding::Plain => Ok(key),
+            KeyEncoding::Base58 => {
+                let bytes = bs58::decode(&key).into_vec().map_err(|err| {
+                    invalid_key_encoding("key", format!("invalid base58 key `{}`: {}.", key, err))
+                })?;
+                String::from_utf8(bytes).map_err(|_| {
+                    invalid_key_encoding(
+                        "key",
+                        format!("base58 key `{}` is not a valid UTF-8 string.", key),
+                    )
+                })
+            }
+        }
+    }
+}
+
+fn invalid_key_encoding(parameter: &str, reason: String) -> Rejection {
+    let details = ErrorDetails {
+        parameter: parameter.to_string(),
+        reason,
+    };
+    warp::reject::custom::<AppError>(AppError::new_validation_error(
+        ValidationErrorCode::InvalidParamenterValue,
+        details,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base58() -> KeyEncoding {
+        let mut m = HashMap::new();
+        m.insert("key_encoding".to_string(), "base58".to_string());
+        KeyEncoding::from_hashmap(&m).unwrap()
+    }
+
+    fn details(rejection: Rejection) -> (u32, ErrorDetails) {
+        match rejection.find::<AppError>() {
+            Some(AppError::ValidationError(_, code, Some(details))) => (*code, details.clone()),
+            err => panic!("unexpected rejection {:?}", err),
+        }
+    }
+
+    #[test]
+    fn decodes_base58_keys() {
+        let key = "price/7 %";
+        let encoded = bs58::encode(key).into_string();
+
+        assert_eq!(base58().decode(encoded).unwrap(), key);
+        assert_eq!(KeyEncoding::default().decode(key.into()).unwrap(), key);
+    }
+
+    #[test]
+    fn rejects_invalid_base58_key() {
+        // `0` isn't in the base58 alphabet
+        let (code, details) = details(base58().decode("3P0k".into()).unwrap_err());
+
+        assert_eq!(code, ValidationErrorCode::InvalidParamenterValue as u32);
+        assert_eq!(details.parameter, "key");
+        assert!(details.reason.starts_with("invalid base58 key `3P0k`"));
+    }
+}
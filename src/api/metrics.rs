@@ -0,0 +1,72 @@
+use once_cell::sync::Lazy;
+use prometheus::{exponential_buckets, HistogramOpts, HistogramVec, IntCounterVec, Opts};
+use warp::hyper::body::HttpBody;
+use warp::reply::{Reply, Response};
+
+pub static RESPONSE_BYTES: Lazy<HistogramVec> = Lazy::new(|| {
+    HistogramVec::new(
+        HistogramOpts::new("response_bytes", "Size of response bodies in bytes")
+            .buckets(exponential_buckets(64.0, 4.0, 10).unwrap()),
+        &["route"],
+    )
+    .unwrap()
+});
+
+pub static VALIDATION_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("validation_errors_total", "Requests rejected by validation"),
+        &["parameter", "code"],
+    )
+    .unwrap()
+});
+
+/// Counts a validation rejection. Indices are dropped from the parameter path,
+/// so that `filter.and[0].key` and `filter.and[1].key` share a label.
+pub fn observe_validation_error(parameter: &str, code: u32) {
+    let mut label = String::with_capacity(parameter.len());
+    let mut in_brackets = false;
+    for c in parameter.chars() {
+        match c {
+            '[' => in_brackets = true,
+            ']' => in_brackets = false,
+            _ if in_brackets => continue,
+            _ => {}
+        }
+        label.push(c);
+    }
+    VALIDATION_ERRORS
+        .with_label_values(&[&label, &code.to_string()])
+        .inc();
+}
+
+/// Observes the body size of a route response.
+/// Streamed bodies (e.g. CSV) have no known size and are not observed.
+pub fn observe_response_bytes(route: &str, reply: impl Reply) -> Response {
+    let res = reply.into_response();
+    if let Some(size) = res.body().size_hint().exact() {
+        RESPONSE_BYTES
+            .with_label_values(&[route])
+            .observe(size as f64);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observes_response_size_by_route() {
+        // a route label of its own, so that other tests don't add samples to it
+        let histogram = || RESPONSE_BYTES.with_label_values(&["metrics_test"]);
+        let count = histogram().get_sample_count();
+        let sum = histogram().get_sample_sum();
+
+        let res = observe_response_bytes("metrics_test", warp::reply::json(&vec![1, 2, 3]));
+
+        assert_eq!(res.status(), 200);
+        assert_eq!(histogram().get_sample_count(), count + 1);
+        // `[1,2,3]`
+        assert_eq!(histogram().get_sample_sum(), sum + 7.0);
+    }
+}
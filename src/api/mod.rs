@@ -1,12 +1,29 @@
+mod budget;
+mod capabilities;
+mod coalescing;
+mod csv;
 mod errors;
+pub mod fields;
 pub mod historical;
+mod key_encoding;
+mod metrics;
 pub mod parsing;
+mod protobuf;
+pub mod rate_limit;
 mod sql;
 
-use serde::{Serialize, Serializer};
-use std::collections::HashMap;
+use serde::{
+    ser::{SerializeMap, SerializeStruct},
+    Serialize, Serializer,
+};
+use std::collections::{BTreeMap, HashMap};
 use tracing::{instrument, trace_span};
 use warp::{
+    filters::BoxedFilter,
+    http::{
+        header::{HeaderValue, CONTENT_TYPE, RETRY_AFTER},
+        StatusCode,
+    },
     reply::{json, Reply, Response},
     Filter, Rejection,
 };
@@ -17,11 +34,24 @@ use wavesexchange_warp::error::{
 use wavesexchange_warp::log::access;
 use wavesexchange_warp::MetricsWarpBuilder;
 
+use crate::config::{Config, RouteGroup};
 use crate::data_entries;
+use budget::ResponseBudget;
+use coalescing::Coalescer;
 use errors::*;
+use fields::{Field, Fields};
 use historical::HistoricalRequestParams;
-use itertools::Itertools;
-use parsing::{Entry, MgetByAddress, MgetEntries, SearchRequest};
+use key_encoding::KeyEncoding;
+use parsing::{
+    AddressFilter, AddressesCountRequest, AddressesSearchRequest, AndFilter, ChangesParams, Entry,
+    FragmentType, FragmentValueType, GroupedParams, KeyFilter, KeyFragmentFilter, KeyHistoryParams,
+    KeyOperation, MapParams, MgetByAddress, MgetEntries, Operation, PageParams, RequestFilter,
+    RequestSort, SearchQuery, SearchRequest, SortItem, SortItemDirection, ToType, ValueEverParams,
+    FRAGMENT_POSITION_MAX,
+};
+use rate_limit::RateLimiter;
+
+pub use sql::SqlContext;
 
 const ERROR_CODES_PREFIX: u16 = 95; // internal service
 const KEYS_LIMIT: u16 = 1000;
@@ -48,13 +78,229 @@ impl Serialize for DataEntryType {
     }
 }
 
+impl ToType for DataEntryType {
+    fn to_type(&self) -> String {
+        match self {
+            DataEntryType::BinaryVal(_) => "binary".to_string(),
+            DataEntryType::BoolVal(_) => "bool".to_string(),
+            DataEntryType::IntVal(_) => "integer".to_string(),
+            DataEntryType::StringVal(_) => "string".to_string(),
+        }
+    }
+}
+
+/// Value tagged with the type of the column holding it: `{"type": "string", "data": ""}`.
+struct TypedValue<'a>(&'a DataEntryType);
+
+impl Serialize for TypedValue<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut s = serializer.serialize_struct("TypedValue", 2)?;
+        s.serialize_field("type", &self.0.to_type())?;
+        s.serialize_field("data", self.0)?;
+        s.end()
+    }
+}
+
+/// Entry with its value serialized as `TypedValue`, requested via `?typed=true`.
+#[derive(Serialize)]
+struct TypedDataEntry<'a> {
+    #[serde(flatten)]
+    entry: &'a DataEntry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<TypedValue<'a>>,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct DataEntry {
-    address: String,
-    key: String,
-    height: i32,
-    value: DataEntryType,
-    fragments: Fragments,
+    // not exposed, only the id derived from it is
+    #[serde(skip)]
+    uid: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    // omitted when projected out via `fields`, null when the block row is missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<Option<i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<DataEntryType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fragments: Option<Fragments>,
+    // size of the full value, also when it is truncated or not selected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_size: Option<i32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    // the value is serialized as `TypedValue`
+    #[serde(skip)]
+    typed: bool,
+    // value replaced by this version, with `include_previous=true` in the change feed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_value: Option<DataEntryType>,
+    // fragments as `frag_N` and `value_frag_N` fields instead of `fragments`, with `flat=true`
+    #[serde(flatten)]
+    flat_fragments: Option<FlatFragments>,
+    // number of versions of the key, with `include_version_count=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version_count: Option<i64>,
+    // height of the first version of the key, with `include_first_height=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_height: Option<i32>,
+}
+
+/// Fragments of all positions, absent ones as nulls, serialized in position order.
+#[derive(Clone, Debug)]
+struct FlatFragments(Vec<(String, serde_json::Value)>);
+
+impl Serialize for FlatFragments {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut m = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in &self.0 {
+            m.serialize_entry(name, value)?;
+        }
+        m.end()
+    }
+}
+
+impl DataEntry {
+    /// Drops the fields that were not requested.
+    fn with_fields(self, fields: &Fields) -> Self {
+        Self {
+            address: self.address.filter(|_| fields.contains(&Field::Address)),
+            key: self.key.filter(|_| fields.contains(&Field::Key)),
+            height: self.height.filter(|_| fields.contains(&Field::Height)),
+            value: self.value.filter(|_| fields.contains(&Field::Value)),
+            previous_value: self
+                .previous_value
+                .filter(|_| fields.contains(&Field::Value)),
+            fragments: self
+                .fragments
+                .filter(|_| fields.contains(&Field::Fragments)),
+            value_size: self
+                .value_size
+                .filter(|_| fields.contains(&Field::ValueSize)),
+            ..self
+        }
+    }
+
+    /// Sets a stable id of the entry version, the md5 of `"{address}:{uid}:{key}"`.
+    /// Computed before `with_fields`, which may drop the address and key.
+    fn with_id(self, include_id: bool) -> Self {
+        if !include_id {
+            return self;
+        }
+        debug_assert!(
+            self.address.is_some() && self.key.is_some(),
+            "the id is computed before `with_fields`"
+        );
+        let id = format!(
+            "{:x}",
+            md5::compute(format!(
+                "{}:{}:{}",
+                self.address.as_deref().unwrap_or_default(),
+                self.uid,
+                self.key.as_deref().unwrap_or_default()
+            ))
+        );
+        Self {
+            id: Some(id),
+            ..self
+        }
+    }
+
+    fn with_previous_value(self, previous_values: &mut HashMap<i64, DataEntryType>) -> Self {
+        Self {
+            previous_value: previous_values.remove(&self.uid),
+            ..self
+        }
+    }
+
+    /// Sets the number of versions of the key, looked up by address and key before `with_fields` drops them.
+    fn with_version_count(self, version_counts: &HashMap<(String, String), i64>) -> Self {
+        let version_count = match (&self.address, &self.key) {
+            (Some(address), Some(key)) => {
+                version_counts.get(&(address.clone(), key.clone())).copied()
+            }
+            _ => None,
+        };
+        Self {
+            version_count,
+            ..self
+        }
+    }
+
+    /// Sets the height the key was created at, looked up like `with_version_count`.
+    fn with_first_height(self, first_heights: &HashMap<(String, String), i32>) -> Self {
+        let first_height = match (&self.address, &self.key) {
+            (Some(address), Some(key)) => {
+                first_heights.get(&(address.clone(), key.clone())).copied()
+            }
+            _ => None,
+        };
+        Self {
+            first_height,
+            ..self
+        }
+    }
+
+    /// Replaces `fragments` with a scalar field per fragment position.
+    fn with_flat(self, flat: bool) -> Self {
+        let fragments = match self.fragments {
+            Some(fragments) if flat => fragments,
+            fragments => return Self { fragments, ..self },
+        };
+
+        let mut flat_fragments = vec![];
+        for position in 0..=FRAGMENT_POSITION_MAX as usize {
+            let value = match fragments.key.get(position) {
+                Some(DataEntryFragment::String { value }) => value.clone().into(),
+                Some(DataEntryFragment::Integer { value }) => (*value).into(),
+                None => serde_json::Value::Null,
+            };
+            flat_fragments.push((format!("frag_{}", position), value));
+        }
+        for position in 0..=FRAGMENT_POSITION_MAX as usize {
+            let value = match fragments.value.get(position) {
+                Some(DataEntryValueFragment::String { value }) => value.clone().into(),
+                Some(DataEntryValueFragment::Integer { value }) => (*value).into(),
+                None => serde_json::Value::Null,
+            };
+            flat_fragments.push((format!("value_frag_{}", position), value));
+        }
+
+        Self {
+            fragments: None,
+            flat_fragments: Some(FlatFragments(flat_fragments)),
+            ..self
+        }
+    }
+
+    fn with_typed_value(self, typed: bool) -> Self {
+        Self { typed, ..self }
+    }
+
+    /// Cuts string values down to `max_len` bytes, never splitting a multibyte char.
+    fn with_max_value_len(mut self, max_len: Option<usize>) -> Self {
+        if let (Some(max_len), Some(DataEntryType::StringVal(v))) = (max_len, &mut self.value) {
+            if v.len() > max_len {
+                let boundary = (0..=max_len)
+                    .rev()
+                    .find(|i| v.is_char_boundary(*i))
+                    .unwrap_or(0);
+                v.truncate(boundary);
+                self.truncated = true;
+            }
+        }
+        self
+    }
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -64,8 +310,17 @@ pub struct Fragments {
 }
 
 impl Reply for DataEntry {
-    fn into_response(self) -> Response {
-        json(&self).into_response()
+    fn into_response(mut self) -> Response {
+        if self.typed {
+            let value = self.value.take();
+            json(&TypedDataEntry {
+                entry: &self,
+                value: value.as_ref().map(TypedValue),
+            })
+            .into_response()
+        } else {
+            json(&self).into_response()
+        }
     }
 }
 
@@ -87,6 +342,61 @@ pub enum DataEntryValueFragment {
 pub struct DataEntriesResponse {
     entries: Vec<DataEntry>,
     has_next_page: bool,
+    // effective paging, with defaults applied
+    limit: u64,
+    offset: u64,
+    // entries were cut short by the response budget, the next page starts after the last one
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    partial: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct GroupedResponse {
+    entries: Vec<DataEntry>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HeightsResponse {
+    heights: Vec<i32>,
+    has_next_page: bool,
+    limit: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ChangesResponse {
+    entries: Vec<DataEntry>,
+    // passed as `cursor` to get the next page, absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<i64>,
+    limit: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct KeyHistoryResponse {
+    entries: Vec<DataEntry>,
+    // passed as `cursor` to get the next page, absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<i64>,
+    limit: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ExplainResponse {
+    // as returned by `EXPLAIN (FORMAT JSON)`
+    plan: serde_json::Value,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CountResponse {
+    count: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AddressesResponse {
+    addresses: Vec<String>,
+    has_next_page: bool,
+    limit: u64,
+    offset: u64,
 }
 
 impl Reply for DataEntriesResponse {
@@ -95,9 +405,21 @@ impl Reply for DataEntriesResponse {
     }
 }
 
-pub async fn start(port: u16, metrics_port: u16, repo: data_entries::Repo) {
+pub async fn start(config: &Config, rate_limiter: Option<RateLimiter>, repo: data_entries::Repo) {
+    let enabled = |group| !config.disabled_routes.contains(&group);
+    let admin_token = config
+        .admin_token
+        .clone()
+        .filter(|_| enabled(RouteGroup::Admin));
+
     let with_repo = warp::any().map(move || repo.clone());
 
+    let budget = ResponseBudget::new(config.max_response_bytes);
+    let with_budget = warp::any().map(move || budget);
+
+    let coalescer = Coalescer::new();
+    let with_coalescer = warp::any().map(move || coalescer.clone());
+
     let request_tracing = warp::trace(|info| {
         let req_id = info
             .request_headers()
@@ -112,58 +434,133 @@ pub async fn start(port: u16, metrics_port: u16, repo: data_entries::Repo) {
         )
     });
 
-    let error_handler = handler(ERROR_CODES_PREFIX, |err| match err {
-        AppError::ValidationError(_error_message, _error_code, error_details) => {
-            validation::invalid_parameter(
-                ERROR_CODES_PREFIX,
-                error_details.to_owned().map(|details| details.into()),
-            )
-        }
-        errors::AppError::DbError(error_message)
-            if error_message == "canceling statement due to statement timeout" =>
-        {
-            error!("{:?}", err);
-            timeout(ERROR_CODES_PREFIX)
-        }
-        _ => {
-            error!("{:?}", err);
-            internal(ERROR_CODES_PREFIX)
-        }
+    let error_handler = handler(ERROR_CODES_PREFIX, app_error_response);
+
+    let search_request = warp::body::json().and_then(|req: serde_json::Value| async move {
+        let req_string = req.to_string();
+        let jd = &mut serde_json::Deserializer::from_str(&req_string);
+        serde_path_to_error::deserialize(jd)
+            .map_err(|err| warp::reject::custom(AppError::from(err)))
+            .and_then(|req: SearchRequest| match req.is_valid() {
+                Ok(_) => Ok(req),
+                Err(err) => Err(warp::reject::custom(err)),
+            })
     });
 
     let search = warp::path::path("search")
         .and(warp::path::end())
         .and(warp::post())
+        .and(without_explain())
+        .and(json_content_type())
+        .and(search_request)
+        .and(with_repo.clone())
+        .and(with_budget.clone())
+        .and(format_query_params())
+        .and_then(search_handler)
+        .and(warp::header::optional::<String>("accept"))
+        .map(entries_reply)
+        .map(|res| metrics::observe_response_bytes("search", res));
+
+    // plans are only shown to admins, the search route leaves `explain` requests to this one
+    let search_explain = match admin_token.clone() {
+        Some(token) => warp::path::path("search")
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(explain_analyze())
+            .and(admin_auth(token))
+            .and(json_content_type())
+            .and(search_request)
+            .and(with_repo.clone())
+            .and(warp::query::<HashMap<String, String>>())
+            .and_then(search_explain_handler)
+            .map(|res| metrics::observe_response_bytes("search_explain", res))
+            .boxed(),
+        None => warp::any()
+            .and_then(|| async { Err::<Response, Rejection>(warp::reject::not_found()) })
+            .boxed(),
+    };
+
+    // simple searches, cacheable as they are passed in the query
+    let search_get = warp::path::path("search")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(serde_qs::warp::query::<SearchQuery>(serde_qs::Config::new(
+            5, false,
+        )))
+        .and_then(|query: SearchQuery| async move {
+            query.into_search_request().map_err(warp::reject::custom)
+        })
+        .and(with_repo.clone())
+        .and(with_budget.clone())
+        .and(format_query_params())
+        .and_then(search_handler)
+        .and(warp::header::optional::<String>("accept"))
+        .map(entries_reply)
+        .map(|res| metrics::observe_response_bytes("search_get", res));
+
+    let search_addresses = warp::path!("search" / "addresses")
+        .and(warp::post())
+        .and(json_content_type())
+        .and(
+            warp::body::json().and_then(|req: serde_json::Value| async move {
+                let req_string = req.to_string();
+                let jd = &mut serde_json::Deserializer::from_str(&req_string);
+                serde_path_to_error::deserialize(jd)
+                    .map_err(|err| warp::reject::custom(AppError::from(err)))
+                    .and_then(|req: AddressesSearchRequest| match req.is_valid() {
+                        Ok(_) => Ok(req),
+                        Err(err) => Err(warp::reject::custom(err)),
+                    })
+            }),
+        )
+        .and(with_repo.clone())
+        .and_then(search_addresses_handler)
+        .map(|res| metrics::observe_response_bytes("search_addresses", res));
+
+    let count_addresses = warp::path!("search" / "addresses" / "count")
+        .and(warp::post())
+        .and(json_content_type())
         .and(
             warp::body::json().and_then(|req: serde_json::Value| async move {
                 let req_string = req.to_string();
                 let jd = &mut serde_json::Deserializer::from_str(&req_string);
                 serde_path_to_error::deserialize(jd)
                     .map_err(|err| warp::reject::custom(AppError::from(err)))
-                    .and_then(|req: SearchRequest| match req.is_valid() {
+                    .and_then(|req: AddressesCountRequest| match req.is_valid() {
                         Ok(_) => Ok(req),
                         Err(err) => Err(warp::reject::custom(err)),
                     })
             }),
         )
         .and(with_repo.clone())
-        .and_then(search_handler);
+        .and_then(count_addresses_handler)
+        .map(|res| metrics::observe_response_bytes("count_addresses", res));
 
     let mget_entries = warp::path::path("entries")
         .and(warp::path::end())
         .and(warp::post())
+        .and(json_content_type())
         .and(warp::body::json::<MgetEntries>())
         .and(with_repo.clone())
-        .and(warp::query::<HashMap<String, String>>())
-        .and_then(mget_handler);
+        .and(with_budget.clone())
+        .and(format_query_params())
+        .and_then(mget_handler)
+        .and(warp::header::optional::<String>("accept"))
+        .map(mget_reply)
+        .map(|res| metrics::observe_response_bytes("mget_entries", res));
 
     let post_by_address = warp::path!("entries" / String)
         .and(warp::path::end())
         .and(warp::post())
+        .and(json_content_type())
         .and(warp::body::json::<MgetByAddress>())
         .and(with_repo.clone())
-        .and(warp::query::<HashMap<String, String>>())
-        .and_then(mget_by_address_handler);
+        .and(with_budget.clone())
+        .and(format_query_params())
+        .and_then(mget_by_address_handler)
+        .and(warp::header::optional::<String>("accept"))
+        .map(mget_reply)
+        .map(|res| metrics::observe_response_bytes("post_by_address", res));
 
     let mget_by_address = warp::path!("entries" / String)
         .and(warp::path::end())
@@ -172,88 +569,774 @@ pub async fn start(port: u16, metrics_port: u16, repo: data_entries::Repo) {
             serde_qs::Config::new(5, false),
         ))
         .and(with_repo.clone())
+        .and(with_budget.clone())
+        .and(format_query_params())
+        .and_then(mget_by_address_handler)
+        .and(warp::header::optional::<String>("accept"))
+        .map(mget_reply)
+        .map(|res| metrics::observe_response_bytes("mget_by_address", res));
+
+    // must be matched before get_by_address_key, which would take `grouped` as a key
+    let grouped = warp::path!("entries" / String / "grouped")
+        .and(warp::get())
+        .and(serde_qs::warp::query::<GroupedParams>(
+            serde_qs::Config::new(5, false),
+        ))
+        .and(with_repo.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(grouped_handler)
+        .map(|res| metrics::observe_response_bytes("grouped", res));
+
+    // must be matched before get_by_address_key, which would take `map` as a key
+    let address_map = warp::path!("entries" / String / "map")
+        .and(warp::get())
+        .and(serde_qs::warp::query::<MapParams>(serde_qs::Config::new(
+            5, false,
+        )))
+        .and(with_repo.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(address_map_handler)
+        .map(|res| metrics::observe_response_bytes("address_map", res));
+
+    let key_history = warp::path!("entries" / String / String / "history")
+        .and(warp::get())
+        .and(serde_qs::warp::query::<KeyHistoryParams>(
+            serde_qs::Config::new(5, false),
+        ))
+        .and(with_repo.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(key_history_handler)
+        .map(|res| metrics::observe_response_bytes("key_history", res));
+
+    let value_ever = warp::path!("entries" / String / String / "ever")
+        .and(warp::get())
+        .and(serde_qs::warp::query::<ValueEverParams>(
+            serde_qs::Config::new(5, false),
+        ))
+        .and(with_repo.clone())
         .and(warp::query::<HashMap<String, String>>())
-        .and_then(mget_by_address_handler);
+        .and_then(value_ever_handler)
+        .map(|res| metrics::observe_response_bytes("value_ever", res));
 
     let get_by_address_key = warp::path!("entries" / String / String)
         .and(warp::path::end())
         .and(warp::get())
         .and(with_repo.clone())
+        .and(with_coalescer.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(get_by_address_key_handler)
+        .map(|res| metrics::observe_response_bytes("get_by_address_key", res));
+
+    let by_fragment = warp::path!("entries" / String / "by-fragment" / u64 / String)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(serde_qs::warp::query::<PageParams>(serde_qs::Config::new(
+            5, false,
+        )))
+        .and(with_repo.clone())
+        .and(with_budget.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(by_fragment_handler)
+        .map(|res| metrics::observe_response_bytes("by_fragment", res));
+
+    let block_entries = warp::path!("blocks" / i64 / "entries")
+        .and(warp::get())
+        .and(serde_qs::warp::query::<PageParams>(serde_qs::Config::new(
+            5, false,
+        )))
+        .and(with_repo.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(block_entries_handler)
+        .map(|res| metrics::observe_response_bytes("block_entries", res));
+
+    let transaction_entries = warp::path!("tx" / String / "entries")
+        .and(warp::get())
+        .and(serde_qs::warp::query::<PageParams>(serde_qs::Config::new(
+            5, false,
+        )))
+        .and(with_repo.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(transaction_entries_handler)
+        .map(|res| metrics::observe_response_bytes("transaction_entries", res));
+
+    let changes = warp::path!("changes")
+        .and(warp::get())
+        .and(serde_qs::warp::query::<ChangesParams>(
+            serde_qs::Config::new(5, false),
+        ))
+        .and(with_repo.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(changes_handler)
+        .map(|res| metrics::observe_response_bytes("changes", res));
+
+    let changes_at_height = warp::path!("changes" / "at" / i32)
+        .and(warp::get())
+        .and(serde_qs::warp::query::<PageParams>(serde_qs::Config::new(
+            5, false,
+        )))
+        .and(with_repo.clone())
         .and(warp::query::<HashMap<String, String>>())
-        .and_then(get_by_address_key_handler);
+        .and_then(changes_at_height_handler)
+        .map(|res| metrics::observe_response_bytes("changes_at_height", res));
+
+    let capabilities = capabilities::Capabilities::new(config);
+    let capabilities = warp::path!("capabilities")
+        .and(warp::get())
+        .map(move || json(&capabilities));
+
+    // requests to /admin are authorized before route matching,
+    // so a missing or wrong token never reveals which admin routes exist
+    let admin = match admin_token {
+        Some(token) => {
+            let cache_flush = warp::path!("cache" / "flush")
+                .and(warp::post())
+                .and(with_coalescer.clone())
+                .map(cache_flush_handler);
+
+            warp::path("admin")
+                .and(admin_auth(token))
+                .and(cache_flush)
+                .boxed()
+        }
+        None => warp::any()
+            .and_then(|| async { Err::<Response, Rejection>(warp::reject::not_found()) })
+            .boxed(),
+    };
+
+    let search = mounted(enabled(RouteGroup::Search), search);
+    let search_get = mounted(enabled(RouteGroup::Search), search_get);
+    let search_explain = mounted(enabled(RouteGroup::Search), search_explain);
+    let search_addresses = mounted(enabled(RouteGroup::Search), search_addresses);
+    let by_fragment = mounted(enabled(RouteGroup::Search), by_fragment);
+    let mget_entries = mounted(enabled(RouteGroup::Mget), mget_entries);
+    let mget_by_address = mounted(enabled(RouteGroup::Mget), mget_by_address);
+    let post_by_address = mounted(enabled(RouteGroup::Mget), post_by_address);
+    let get_by_address_key = mounted(enabled(RouteGroup::Mget), get_by_address_key);
+    let key_history = mounted(enabled(RouteGroup::Mget), key_history);
+    let value_ever = mounted(enabled(RouteGroup::Mget), value_ever);
+    let address_map = mounted(enabled(RouteGroup::Mget), address_map);
+    let count_addresses = mounted(enabled(RouteGroup::Aggregate), count_addresses);
+    let grouped = mounted(enabled(RouteGroup::Aggregate), grouped);
+    let block_entries = mounted(enabled(RouteGroup::Changes), block_entries);
+    let transaction_entries = mounted(enabled(RouteGroup::Changes), transaction_entries);
+    let changes = mounted(enabled(RouteGroup::Changes), changes);
+    let changes_at_height = mounted(enabled(RouteGroup::Changes), changes_at_height);
 
     let log = warp::log::custom(access);
 
-    info!("Starting web server at 0.0.0.0:{}", port);
+    info!("Starting web server at 0.0.0.0:{}", config.port);
 
-    let routes = search
-        .or(mget_entries)
-        .or(mget_by_address)
-        .or(post_by_address)
-        .or(get_by_address_key)
+    // metrics are served on their own port and are never limited
+    let routes = rate_limit(rate_limiter)
+        .and(
+            search
+                .or(search_get)
+                .or(search_explain)
+                .or(search_addresses)
+                .or(count_addresses)
+                .or(mget_entries)
+                .or(mget_by_address)
+                .or(post_by_address)
+                .or(grouped)
+                .or(address_map)
+                .or(get_by_address_key)
+                .or(key_history)
+                .or(value_ever)
+                .or(by_fragment)
+                .or(block_entries)
+                .or(transaction_entries)
+                .or(changes)
+                .or(changes_at_height)
+                .or(capabilities)
+                .or(admin),
+        )
         .recover(move |rej| {
             error_handler_with_serde_qs(ERROR_CODES_PREFIX, error_handler.clone())(rej)
         })
+        .recover(not_found_handler);
+
+    // error responses are indented too, so the flag is applied around the recovery
+    let routes = pretty(config.pretty_responses)
+        .and(routes)
+        .and_then(prettified)
         .with(request_tracing)
         .with(log);
 
     MetricsWarpBuilder::new()
         .with_main_routes(routes)
-        .with_main_routes_port(port)
-        .with_metrics_port(metrics_port)
+        .with_main_routes_port(config.port)
+        .with_metrics_port(config.metrics_port)
+        .with_metric(&*metrics::RESPONSE_BYTES)
+        .with_metric(&*metrics::VALIDATION_ERRORS)
         .run_async()
         .await;
 }
 
-fn decode_uri_string(s: String) -> Result<String, Rejection> {
-    percent_encoding::percent_decode(s.as_bytes())
-        .decode_utf8()
-        .map(|s| s.to_string())
-        .map_err(|error| {
-            warp::reject::custom::<AppError>(AppError::DecodePathError(error.to_string()))
-        })
-}
-
-#[derive(Debug, Serialize)]
-struct MgetResponse {
-    entries: Vec<Option<DataEntry>>,
+/// Responds to unmatched routes with a json error, like to any other error.
+async fn not_found_handler(rej: Rejection) -> Result<Response, Rejection> {
+    if rej.is_not_found() {
+        Ok(error_response(
+            StatusCode::NOT_FOUND,
+            ErrorCode::NotFound as u32,
+            "Not found",
+            None,
+        ))
+    } else {
+        Err(rej)
+    }
 }
 
-impl Reply for MgetResponse {
-    fn into_response(self) -> Response {
-        json(&self).into_response()
+/// Responds to an `AppError` rejection, validation errors are counted by parameter and code.
+fn app_error_response(err: &AppError) -> Response {
+    if let AppError::ValidationError(_error_message, error_code, Some(error_details)) = err {
+        metrics::observe_validation_error(&error_details.parameter, *error_code);
     }
-}
 
-impl From<data_entries::DataEntry> for DataEntry {
-    fn from(v: data_entries::DataEntry) -> Self {
-        let key_fragments = (&v).into();
-        let value_fragments = (&v).into();
-        let value;
-        if let Some(v) = v.value_binary {
-            value = DataEntryType::BinaryVal(v);
-        } else if let Some(v) = v.value_bool {
-            value = DataEntryType::BoolVal(v);
-        } else if let Some(v) = v.value_integer {
-            value = DataEntryType::IntVal(v);
-        } else {
-            // unwrap is safe because of data entry value is not null
-            value = DataEntryType::StringVal(v.value_string.unwrap());
+    match err {
+        AppError::ValidationError(_error_message, error_code, error_details)
+            if *error_code == ValidationErrorCode::ConflictingParameters as u32 =>
+        {
+            error_response(
+                StatusCode::CONFLICT,
+                *error_code,
+                "Conflicting parameters",
+                error_details.to_owned(),
+            )
         }
-        let fragments = Fragments {
-            key: key_fragments,
-            value: value_fragments,
-        };
-        Self {
-            address: v.address.clone(),
-            key: v.key.clone(),
-            height: v.height.clone(),
-            value,
-            fragments,
+        AppError::ValidationError(_error_message, _error_code, error_details) => {
+            validation::invalid_parameter(
+                ERROR_CODES_PREFIX,
+                error_details.to_owned().map(|details| details.into()),
+            )
+        }
+        AppError::Unauthorized => error_response(
+            StatusCode::UNAUTHORIZED,
+            ErrorCode::Unauthorized as u32,
+            "Unauthorized",
+            None,
+        ),
+        AppError::NotFound => error_response(
+            StatusCode::NOT_FOUND,
+            ErrorCode::NotFound as u32,
+            "Not found",
+            None,
+        ),
+        AppError::IndexerBehind(last_indexed_height) => error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::IndexerBehind as u32,
+            "Indexer is behind the requested height",
+            Some(ErrorDetails {
+                parameter: "min_height".to_string(),
+                reason: format!("last indexed height is {}", last_indexed_height),
+            }),
+        ),
+        AppError::ResponseTooLarge(max_bytes) => error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::ResponseTooLarge as u32,
+            "Response is too large",
+            Some(ErrorDetails {
+                parameter: "limit".to_string(),
+                reason: format!(
+                    "response exceeds {} bytes, request fewer entries",
+                    max_bytes
+                ),
+            }),
+        ),
+        AppError::UnsupportedMediaType(content_type) => error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::UnsupportedMediaType as u32,
+            "Unsupported media type",
+            Some(ErrorDetails {
+                parameter: "content-type".to_string(),
+                reason: format!("expected application/json, got {}", content_type),
+            }),
+        ),
+        AppError::RateLimited(retry_after) => {
+            let mut res = error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                ErrorCode::RateLimited as u32,
+                "Too many requests",
+                None,
+            );
+            res.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(*retry_after));
+            res
+        }
+        AppError::DbUnavailable(retry_after) => {
+            let mut res = error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::DbUnavailable as u32,
+                "Database is unavailable",
+                None,
+            );
+            res.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(*retry_after));
+            res
+        }
+        errors::AppError::DbError(error_message)
+            if error_message == "canceling statement due to statement timeout" =>
+        {
+            error!("{:?}", err);
+            timeout(ERROR_CODES_PREFIX)
+        }
+        _ => {
+            error!("{:?}", err);
+            internal(ERROR_CODES_PREFIX)
         }
     }
 }
 
+/// Query params of the routes answering in csv or protobuf too:
+/// those formats always carry key fragments, so `include_fragments` is implied for them.
+fn format_query_params(
+) -> impl Filter<Extract = (HashMap<String, String>,), Error = Rejection> + Clone {
+    warp::query::<HashMap<String, String>>()
+        .and(warp::header::optional::<String>("accept"))
+        .map(
+            |mut params: HashMap<String, String>, accept: Option<String>| {
+                if ResponseFormat::from_accept(&accept) != ResponseFormat::Json {
+                    params.insert("include_fragments".to_string(), "true".to_string());
+                }
+                params
+            },
+        )
+}
+
+/// Body format of the search routes, selected by the `accept` header, json by default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ResponseFormat {
+    Json,
+    Csv,
+    Protobuf,
+}
+
+impl ResponseFormat {
+    const ALL: [ResponseFormat; 3] = [
+        ResponseFormat::Json,
+        ResponseFormat::Csv,
+        ResponseFormat::Protobuf,
+    ];
+
+    fn from_accept(accept: &Option<String>) -> Self {
+        if csv::is_csv_accepted(accept) {
+            ResponseFormat::Csv
+        } else if protobuf::is_protobuf_accepted(accept) {
+            ResponseFormat::Protobuf
+        } else {
+            ResponseFormat::Json
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ResponseFormat::Json => "json",
+            ResponseFormat::Csv => "csv",
+            ResponseFormat::Protobuf => "protobuf",
+        }
+    }
+}
+
+fn entries_reply(res: DataEntriesResponse, accept: Option<String>) -> Response {
+    match ResponseFormat::from_accept(&accept) {
+        ResponseFormat::Json => res.into_response(),
+        ResponseFormat::Csv => res.into_csv_response(),
+        ResponseFormat::Protobuf => res.into_protobuf_response(),
+    }
+}
+
+fn mget_reply(res: MgetResponse, accept: Option<String>) -> Response {
+    if protobuf::is_protobuf_accepted(&accept) {
+        res.into_protobuf_response()
+    } else {
+        res.into_response()
+    }
+}
+
+/// Rejects request bodies declared with a content type other than json,
+/// a missing content type is accepted as json.
+fn json_content_type() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and_then(|content_type: Option<String>| async move {
+            match content_type {
+                Some(content_type)
+                    if !content_type
+                        .split(';')
+                        .next()
+                        .unwrap_or_default()
+                        .trim()
+                        .eq_ignore_ascii_case("application/json") =>
+                {
+                    Err(warp::reject::custom(AppError::UnsupportedMediaType(
+                        content_type,
+                    )))
+                }
+                _ => Ok(()),
+            }
+        })
+        .untuple_one()
+}
+
+/// Whether the json response is to be indented, `pretty=true` is ignored unless allowed by the config.
+fn pretty(
+    enabled: bool,
+) -> impl Filter<Extract = (bool,), Error = std::convert::Infallible> + Clone {
+    warp::query::<HashMap<String, String>>()
+        .or(warp::any().map(HashMap::new))
+        .unify()
+        .map(move |get_params: HashMap<String, String>| {
+            enabled && get_params.get("pretty").map(String::as_str) == Some("true")
+        })
+}
+
+async fn prettified(pretty: bool, reply: impl Reply) -> Result<Response, std::convert::Infallible> {
+    let res = reply.into_response();
+    let is_json = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .map_or(false, |v| v.as_bytes().starts_with(b"application/json"));
+    if !pretty || !is_json {
+        return Ok(res);
+    }
+
+    // bodies of json replies are already in memory and valid json, neither step can fail
+    let (parts, body) = res.into_parts();
+    let body = warp::hyper::body::to_bytes(body)
+        .await
+        .ok()
+        .and_then(|body| serde_json::from_slice::<serde_json::Value>(&body).ok())
+        .and_then(|value| serde_json::to_string_pretty(&value).ok());
+    match body {
+        Some(body) => Ok(Response::from_parts(parts, body.into())),
+        None => Ok(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+    }
+}
+
+/// Limits requests per client, identified by `X-Api-Key`,
+/// or else by the first `X-Forwarded-For` address, or else by the remote address.
+fn rate_limit(
+    rate_limiter: Option<RateLimiter>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("x-api-key")
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::addr::remote())
+        .and_then(
+            move |api_key: Option<String>,
+                  forwarded_for: Option<String>,
+                  remote: Option<std::net::SocketAddr>| {
+                let rate_limiter = rate_limiter.clone();
+                async move {
+                    let rate_limiter = match rate_limiter {
+                        Some(rate_limiter) => rate_limiter,
+                        None => return Ok(()),
+                    };
+                    let client = match (api_key, forwarded_for, remote) {
+                        (Some(api_key), _, _) => format!("key:{}", api_key),
+                        (None, Some(forwarded_for), _) => format!(
+                            "ip:{}",
+                            forwarded_for.split(',').next().unwrap_or_default().trim()
+                        ),
+                        (None, None, Some(remote)) => format!("ip:{}", remote.ip()),
+                        (None, None, None) => "ip:unknown".to_string(),
+                    };
+                    rate_limiter.check(&client).map_err(|retry_after| {
+                        // rounded up, so that clients never retry too early
+                        let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+                        warp::reject::custom(AppError::RateLimited(retry_after))
+                    })
+                }
+            },
+        )
+        .untuple_one()
+}
+
+/// The route when its group is enabled, otherwise a filter rejecting everything as not found.
+fn mounted<T, F>(enabled: bool, route: F) -> BoxedFilter<(Response,)>
+where
+    T: Reply + 'static,
+    F: Filter<Extract = (T,), Error = Rejection> + Clone + Send + Sync + 'static,
+{
+    if enabled {
+        route.map(Reply::into_response).boxed()
+    } else {
+        warp::any()
+            .and_then(|| async { Err::<Response, Rejection>(warp::reject::not_found()) })
+            .boxed()
+    }
+}
+
+fn admin_auth(token: String) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let expected = format!("Bearer {}", token);
+    warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let authorized = authorization.as_deref() == Some(expected.as_str());
+            async move {
+                if authorized {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(AppError::Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+/// Rejects requests with `explain` as not found before their body is read,
+/// which can be read only once, so that they reach the explain route instead.
+fn without_explain() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::query::<HashMap<String, String>>()
+        .and_then(|get_params: HashMap<String, String>| async move {
+            if get_params.contains_key("explain") {
+                Err(warp::reject::not_found())
+            } else {
+                Ok(())
+            }
+        })
+        .untuple_one()
+}
+
+/// Matches requests with `explain=analyze`, other `explain` values are rejected.
+fn explain_analyze() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::query::<HashMap<String, String>>()
+        .and_then(|get_params: HashMap<String, String>| async move {
+            match get_params.get("explain").map(String::as_str) {
+                Some("analyze") => Ok(()),
+                Some(_) => {
+                    let details = ErrorDetails {
+                        parameter: "explain".to_string(),
+                        reason: "the only supported value is `analyze`".to_string(),
+                    };
+                    Err(warp::reject::custom(AppError::new_validation_error(
+                        ValidationErrorCode::InvalidParamenterValue,
+                        details,
+                    )))
+                }
+                None => Err(warp::reject::not_found()),
+            }
+        })
+        .untuple_one()
+}
+
+fn max_value_len_from_hashmap(
+    get_params: &HashMap<String, String>,
+) -> Result<Option<usize>, Rejection> {
+    get_params
+        .get("max_value_len")
+        .map(|max_value_len| {
+            max_value_len.parse::<usize>().map_err(|_| {
+                let details = ErrorDetails {
+                    parameter: "max_value_len".to_string(),
+                    reason: "max_value_len must be a non-negative integer.".to_string(),
+                };
+                warp::reject::custom::<AppError>(AppError::new_validation_error(
+                    ValidationErrorCode::InvalidParamenterValue,
+                    details,
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Groups the items by the position of their key in `input`, keeping the relative order
+/// of the items sharing a key. Items with keys missing from `input` are dropped,
+/// repeated input keys get their items at the first position only.
+fn in_input_order<K, T>(items: Vec<T>, input: &[K], key: impl Fn(&T) -> K) -> Vec<Vec<T>>
+where
+    K: Eq + std::hash::Hash,
+{
+    let mut positions = HashMap::with_capacity(input.len());
+    for (position, k) in input.iter().enumerate() {
+        positions.entry(k).or_insert(position);
+    }
+
+    let mut ordered = input.iter().map(|_| vec![]).collect::<Vec<Vec<T>>>();
+    for item in items {
+        if let Some(&position) = positions.get(&key(&item)) {
+            ordered[position].push(item);
+        }
+    }
+    ordered
+}
+
+fn include_id_from_hashmap(get_params: &HashMap<String, String>) -> Result<bool, Rejection> {
+    bool_from_hashmap(get_params, "include_id")
+}
+
+/// `group_by=address` nests mget entries by address, no other grouping is supported.
+fn group_by_address_from_hashmap(get_params: &HashMap<String, String>) -> Result<bool, Rejection> {
+    match get_params.get("group_by").map(String::as_str) {
+        None => Ok(false),
+        Some("address") => Ok(true),
+        Some(_) => {
+            let details = ErrorDetails {
+                parameter: "group_by".to_string(),
+                reason: "group_by must be address.".to_string(),
+            };
+            Err(warp::reject::custom::<AppError>(
+                AppError::new_validation_error(
+                    ValidationErrorCode::InvalidParamenterValue,
+                    details,
+                ),
+            ))
+        }
+    }
+}
+
+fn flat_from_hashmap(get_params: &HashMap<String, String>) -> Result<bool, Rejection> {
+    bool_from_hashmap(get_params, "flat")
+}
+
+fn typed_from_hashmap(get_params: &HashMap<String, String>) -> Result<bool, Rejection> {
+    bool_from_hashmap(get_params, "typed")
+}
+
+fn bool_from_hashmap(get_params: &HashMap<String, String>, name: &str) -> Result<bool, Rejection> {
+    match get_params.get(name).map(String::as_str) {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(_) => {
+            let details = ErrorDetails {
+                parameter: name.to_string(),
+                reason: format!("{} must be either true or false.", name),
+            };
+            Err(warp::reject::custom::<AppError>(
+                AppError::new_validation_error(
+                    ValidationErrorCode::InvalidParamenterValue,
+                    details,
+                ),
+            ))
+        }
+    }
+}
+
+fn decode_uri_string(s: String) -> Result<String, Rejection> {
+    percent_encoding::percent_decode(s.as_bytes())
+        .decode_utf8()
+        .map(|s| s.to_string())
+        .map_err(|error| {
+            warp::reject::custom::<AppError>(AppError::DecodePathError(error.to_string()))
+        })
+}
+
+#[derive(Debug, Serialize)]
+struct MgetResponse {
+    entries: Vec<Option<DataEntry>>,
+    // replaces the json response with `group_by=address`
+    #[serde(skip)]
+    by_address: Option<EntriesByAddress>,
+}
+
+impl Reply for MgetResponse {
+    fn into_response(self) -> Response {
+        match &self.by_address {
+            Some(by_address) => json(by_address).into_response(),
+            None => json(&self).into_response(),
+        }
+    }
+}
+
+/// Entries nested as `{address: {key: entry}}`, addresses and keys in input order,
+/// missing entries as nulls.
+#[derive(Debug)]
+struct EntriesByAddress(Vec<(String, Vec<(String, Option<DataEntry>)>)>);
+
+impl EntriesByAddress {
+    fn new(input: &[(String, String)], entries: &[Option<DataEntry>]) -> Self {
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        let mut groups: Vec<(String, Vec<(String, Option<DataEntry>)>)> = vec![];
+        for ((address, key), entry) in input.iter().zip(entries) {
+            let position = *positions.entry(address.as_str()).or_insert_with(|| {
+                groups.push((address.clone(), vec![]));
+                groups.len() - 1
+            });
+            let keys = &mut groups[position].1;
+            // a pair requested twice is listed once
+            if !keys.iter().any(|(k, _)| k == key) {
+                keys.push((key.clone(), entry.clone()));
+            }
+        }
+        Self(groups)
+    }
+}
+
+impl Serialize for EntriesByAddress {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        struct Keys<'a>(&'a [(String, Option<DataEntry>)]);
+
+        impl Serialize for Keys<'_> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                let mut m = serializer.serialize_map(Some(self.0.len()))?;
+                for (key, entry) in self.0 {
+                    m.serialize_entry(key, entry)?;
+                }
+                m.end()
+            }
+        }
+
+        let mut m = serializer.serialize_map(Some(self.0.len()))?;
+        for (address, keys) in &self.0 {
+            m.serialize_entry(address, &Keys(keys))?;
+        }
+        m.end()
+    }
+}
+
+impl From<data_entries::DataEntry> for DataEntry {
+    fn from(v: data_entries::DataEntry) -> Self {
+        let key_fragments = (&v).into();
+        let value_fragments = (&v).into();
+        // value is null only when it is projected out via `fields`
+        let value = entry_value(
+            v.value_binary,
+            v.value_bool,
+            v.value_integer,
+            v.value_string,
+        );
+        let fragments = Fragments {
+            key: key_fragments,
+            value: value_fragments,
+        };
+        Self {
+            uid: v.uid,
+            id: None,
+            address: Some(v.address.clone()),
+            key: Some(v.key.clone()),
+            height: Some(v.height),
+            value,
+            fragments: Some(fragments),
+            value_size: v.value_size,
+            truncated: false,
+            typed: false,
+            previous_value: None,
+            flat_fragments: None,
+            version_count: None,
+            first_height: None,
+        }
+    }
+}
+
+fn entry_value(
+    value_binary: Option<Vec<u8>>,
+    value_bool: Option<bool>,
+    value_integer: Option<i64>,
+    value_string: Option<String>,
+) -> Option<DataEntryType> {
+    if let Some(v) = value_binary {
+        Some(DataEntryType::BinaryVal(v))
+    } else if let Some(v) = value_bool {
+        Some(DataEntryType::BoolVal(v))
+    } else if let Some(v) = value_integer {
+        Some(DataEntryType::IntVal(v))
+    } else {
+        value_string.map(DataEntryType::StringVal)
+    }
+}
+
 impl From<&data_entries::DataEntry> for Vec<DataEntryFragment> {
     fn from(v: &data_entries::DataEntry) -> Self {
         let fragments = vec![
@@ -380,76 +1463,267 @@ impl<'a> From<RawFragment<'a>> for Option<DataEntryValueFragment> {
 async fn search_handler(
     req: SearchRequest,
     repo: data_entries::Repo,
+    budget: ResponseBudget,
+    get_params: HashMap<String, String>,
 ) -> Result<DataEntriesResponse, Rejection> {
-    repo.search_data_entries(
-        req.filter.clone(),
-        req.sort.clone(),
-        req.limit + 1,
-        req.offset,
-    )
-    .await
-    .and_then::<DataEntriesResponse, _>(|data_entries| {
-        let has_next_page = data_entries.len() > req.limit as usize;
-        Ok(DataEntriesResponse {
-            entries: data_entries
+    let fields = Fields::from_hashmap(&get_params)?;
+    let max_value_len = max_value_len_from_hashmap(&get_params)?;
+    let include_id = include_id_from_hashmap(&get_params)?;
+    let flat = flat_from_hashmap(&get_params)?;
+
+    reject_if_behind_min_height(&get_params, &repo).await?;
+
+    // limit=0 means an existence check: no rows are fetched, only has_next_page is set
+    if req.limit == 0 {
+        return repo
+            .exists_data_entries(req.filter.clone())
+            .await
+            .map(|exists| DataEntriesResponse {
+                entries: vec![],
+                has_next_page: exists,
+                limit: req.limit,
+                offset: req.offset,
+                partial: false,
+            })
+            .map_err(|err| warp::reject::custom::<AppError>(AppError::from(err)));
+    }
+
+    let sort = match req.key_prefix() {
+        Some(prefix) => req
+            .sort
+            .clone()
+            .map(|sort| sort.with_relevance_key(&prefix)),
+        None => req.sort.clone(),
+    };
+
+    let data_entries = repo
+        .search_data_entries(
+            req.filter.clone(),
+            sort,
+            req.dedup_by.as_ref(),
+            req.limit + 1,
+            req.offset,
+            &fields,
+        )
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    let has_next_page = data_entries.len() > req.limit as usize;
+    let mut data_entries = data_entries
+        .into_iter()
+        .take(req.limit as usize)
+        .collect::<Vec<_>>();
+    if req.preserve_input_order {
+        if let Some(keys) = req.input_keys() {
+            data_entries = in_input_order(data_entries, keys, |de| de.key.clone())
                 .into_iter()
-                .take(req.limit as usize)
-                .map(|de| de.into())
-                .collect(),
-            has_next_page,
+                .flatten()
+                .collect();
+        }
+    }
+    let entries = data_entries
+        .into_iter()
+        .map(|de| {
+            DataEntry::from(de)
+                .with_id(include_id)
+                .with_fields(&fields)
+                .with_max_value_len(max_value_len)
+                .with_flat(flat)
         })
-    })
-    .or_else::<Rejection, _>(|err| {
-        Err(warp::reject::custom::<AppError>(AppError::DbError(err.to_string()).into()).into())
+        .collect();
+    let (entries, partial) = budget.take(entries).map_err(warp::reject::custom)?;
+
+    Ok(DataEntriesResponse {
+        entries,
+        has_next_page: has_next_page || partial,
+        limit: req.limit,
+        offset: req.offset,
+        partial,
     })
 }
 
+#[instrument(skip(req, repo))]
+async fn search_explain_handler(
+    req: SearchRequest,
+    repo: data_entries::Repo,
+    get_params: HashMap<String, String>,
+) -> Result<impl Reply, Rejection> {
+    let fields = Fields::from_hashmap(&get_params)?;
+
+    let sort = match req.key_prefix() {
+        Some(prefix) => req
+            .sort
+            .clone()
+            .map(|sort| sort.with_relevance_key(&prefix)),
+        None => req.sort.clone(),
+    };
+
+    let plan = repo
+        .explain_search_data_entries(
+            req.filter.clone(),
+            sort,
+            req.dedup_by.as_ref(),
+            req.limit + 1,
+            req.offset,
+            &fields,
+        )
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    Ok(json(&ExplainResponse { plan }))
+}
+
+#[instrument(skip(repo))]
+async fn search_addresses_handler(
+    req: AddressesSearchRequest,
+    repo: data_entries::Repo,
+) -> Result<impl Reply, Rejection> {
+    let addresses = repo
+        .search_addresses(req.filter, req.limit + 1, req.offset)
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    let has_next_page = addresses.len() > req.limit as usize;
+    Ok(json(&AddressesResponse {
+        addresses: addresses.into_iter().take(req.limit as usize).collect(),
+        has_next_page,
+        limit: req.limit,
+        offset: req.offset,
+    }))
+}
+
+#[instrument(skip(repo))]
+async fn count_addresses_handler(
+    req: AddressesCountRequest,
+    repo: data_entries::Repo,
+) -> Result<impl Reply, Rejection> {
+    let count = repo
+        .count_addresses(req.filter)
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    Ok(json(&CountResponse { count }))
+}
+
+#[instrument(skip(page, repo))]
+async fn by_fragment_handler(
+    address: String,
+    position: u64,
+    value: String,
+    page: PageParams,
+    repo: data_entries::Repo,
+    budget: ResponseBudget,
+    get_params: HashMap<String, String>,
+) -> Result<DataEntriesResponse, Rejection> {
+    let value = decode_uri_string(value)?;
+
+    let filter = RequestFilter::And(AndFilter(vec![
+        RequestFilter::Address(AddressFilter { value: address }),
+        RequestFilter::Fragment(KeyFragmentFilter {
+            fragment_type: FragmentType::String,
+            position,
+            operation: Operation::Eq,
+            value: FragmentValueType::StringVal(value),
+        }),
+    ]));
+
+    let req = SearchRequest {
+        filter: Some(filter),
+        sort: None,
+        limit: page.limit,
+        offset: page.offset,
+        dedup_by: None,
+        preserve_input_order: false,
+    };
+    req.is_valid().map_err(warp::reject::custom)?;
+
+    search_handler(req, repo, budget, get_params).await
+}
+
 #[instrument(skip(req, repo))]
 async fn mget_handler(
     req: MgetEntries,
     repo: data_entries::Repo,
+    budget: ResponseBudget,
     get_params: HashMap<String, String>,
 ) -> Result<MgetResponse, Rejection> {
     let address_key_pairs = req.address_key_pairs.clone();
 
     let hp = HistoricalRequestParams::from_hashmap(&get_params)?;
+    let fields = Fields::from_hashmap(&get_params)?;
+    let max_value_len = max_value_len_from_hashmap(&get_params)?;
+    let include_id = include_id_from_hashmap(&get_params)?;
+    let flat = flat_from_hashmap(&get_params)?;
+    let group_by_address = group_by_address_from_hashmap(&get_params)?;
+
+    req.is_valid().map_err(warp::reject::custom)?;
 
     let mget_entries = MgetEntries {
         address_key_pairs: address_key_pairs.clone(),
+        filter: None,
     };
 
+    reject_if_before_indexed(&hp, &repo).await?;
+    if hp.is_empty() {
+        reject_if_behind_min_height(&get_params, &repo).await?;
+    }
+    let snapshot = hp.is_empty() && bool_from_hashmap(&get_params, "snapshot")?;
+    let hp = with_snapshot_height(hp, &get_params, &repo).await?;
+
     let e_uids = repo
         .find_entities_uids(&hp, &mget_entries)
         .await
         .or_else::<Rejection, _>(|err| {
-            Err(warp::reject::custom::<AppError>(AppError::DbError(err.to_string()).into()).into())
+            Err(warp::reject::custom::<AppError>(AppError::from(err).into()).into())
         })?;
 
-    reject_if_empty_uids(&hp, &e_uids)?;
+    // missing keys of a snapshot are nulls, like in the latest state
+    if !snapshot {
+        reject_if_empty_uids(&hp, &e_uids)?;
+    }
 
-    repo.mget_data_entries(req, build_historical_sql(&e_uids))
-        .await
-        .and_then(|data_entries| {
-            let mut data_entries_map = data_entries
-                .into_iter()
-                .map(|de| {
-                    let key = (de.address.clone(), de.key.clone());
-                    let de = de.into();
-                    (key, de)
-                })
-                .collect::<HashMap<_, _>>();
-            let entries = address_key_pairs
-                .into_iter()
-                .map(|entry| {
-                    let k = &(entry.address, entry.key);
-                    data_entries_map.remove(k)
-                })
-                .collect::<Vec<Option<DataEntry>>>();
-            Ok(MgetResponse { entries })
-        })
-        .or_else::<Rejection, _>(|err| {
-            Err(warp::reject::custom::<AppError>(AppError::DbError(err.to_string()).into()).into())
+    let data_entries = if snapshot && e_uids.is_empty() {
+        // no uids would read the latest state instead of the snapshot
+        vec![]
+    } else {
+        repo.mget_data_entries(req, &e_uids, &fields)
+            .await
+            .map_err(|err| warp::reject::custom(AppError::from(err)))?
+    };
+    let version_counts = version_counts(&repo, &data_entries, &get_params).await?;
+    let first_heights = first_heights(&repo, &data_entries, &get_params).await?;
+
+    let input = address_key_pairs
+        .into_iter()
+        .map(|entry| (entry.address, entry.key))
+        .collect::<Vec<_>>();
+    let entries = in_input_order(data_entries, &input, |de| {
+        (de.address.clone(), de.key.clone())
+    })
+    .into_iter()
+    .map(|des| {
+        des.into_iter().next().map(|de| {
+            DataEntry::from(de)
+                .with_version_count(&version_counts)
+                .with_first_height(&first_heights)
+                .with_id(include_id)
+                .with_fields(&fields)
+                .with_max_value_len(max_value_len)
+                .with_flat(flat)
         })
+    })
+    .collect::<Vec<Option<DataEntry>>>();
+
+    budget.check(&entries).map_err(warp::reject::custom)?;
+    let by_address = if group_by_address {
+        Some(EntriesByAddress::new(&input, &entries))
+    } else {
+        None
+    };
+    Ok(MgetResponse {
+        entries,
+        by_address,
+    })
 }
 
 #[instrument(skip(query, repo))]
@@ -457,9 +1731,9 @@ async fn mget_by_address_handler(
     address: String,
     query: MgetByAddress,
     repo: data_entries::Repo,
+    budget: ResponseBudget,
     get_params: HashMap<String, String>,
 ) -> Result<MgetResponse, Rejection> {
-    let keys = query.keys.clone();
     if query.keys.len() > KEYS_LIMIT as usize {
         let details = ErrorDetails {
             parameter: "keys".to_string(),
@@ -470,81 +1744,702 @@ async fn mget_by_address_handler(
         ));
     }
 
-    let mget_entries = MgetEntries::from_query_by_address(address, query.keys);
+    let key_encoding = KeyEncoding::from_hashmap(&get_params)?;
+    let keys = query
+        .keys
+        .into_iter()
+        .map(|key| key_encoding.decode(key))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mget_entries = MgetEntries::from_query_by_address(address, keys.clone());
 
     let hp = HistoricalRequestParams::from_hashmap(&get_params)?;
+    let fields = Fields::from_hashmap(&get_params)?;
+    let max_value_len = max_value_len_from_hashmap(&get_params)?;
+    let include_id = include_id_from_hashmap(&get_params)?;
+    let flat = flat_from_hashmap(&get_params)?;
+
+    reject_if_before_indexed(&hp, &repo).await?;
+    if hp.is_empty() {
+        reject_if_behind_min_height(&get_params, &repo).await?;
+    }
+    let snapshot = hp.is_empty() && bool_from_hashmap(&get_params, "snapshot")?;
+    let hp = with_snapshot_height(hp, &get_params, &repo).await?;
 
     let e_uids = repo
         .find_entities_uids(&hp, &mget_entries)
         .await
         .or_else::<Rejection, _>(|err| {
-            Err(warp::reject::custom::<AppError>(AppError::DbError(err.to_string()).into()).into())
+            Err(warp::reject::custom::<AppError>(AppError::from(err).into()).into())
         })?;
 
-    reject_if_empty_uids(&hp, &e_uids)?;
+    // missing keys of a snapshot are nulls, like in the latest state
+    if !snapshot {
+        reject_if_empty_uids(&hp, &e_uids)?;
+    }
 
-    repo.mget_data_entries(mget_entries, build_historical_sql(&e_uids))
-        .await
-        .and_then(|data_entries| {
-            let mut data_entries_map = data_entries
-                .into_iter()
-                .map(|de| {
-                    let key = de.key.clone();
-                    let de = de.into();
-                    (key, de)
-                })
-                .collect::<HashMap<_, _>>();
-            let entries = keys
-                .into_iter()
-                .map(|key| data_entries_map.remove(&key))
-                .collect::<Vec<Option<DataEntry>>>();
-            Ok(MgetResponse { entries })
+    let data_entries = if snapshot && e_uids.is_empty() {
+        // no uids would read the latest state instead of the snapshot
+        vec![]
+    } else {
+        repo.mget_data_entries(mget_entries, &e_uids, &fields)
+            .await
+            .map_err(|err| warp::reject::custom(AppError::from(err)))?
+    };
+    let version_counts = version_counts(&repo, &data_entries, &get_params).await?;
+    let first_heights = first_heights(&repo, &data_entries, &get_params).await?;
+
+    let entries = in_input_order(data_entries, &keys, |de| de.key.clone())
+        .into_iter()
+        .map(|des| {
+            des.into_iter().next().map(|de| {
+                DataEntry::from(de)
+                    .with_version_count(&version_counts)
+                    .with_first_height(&first_heights)
+                    .with_id(include_id)
+                    .with_fields(&fields)
+                    .with_max_value_len(max_value_len)
+                    .with_flat(flat)
+            })
         })
-        .or_else::<Rejection, _>(|err| {
-            Err(warp::reject::custom::<AppError>(AppError::DbError(err.to_string()).into()).into())
+        .collect::<Vec<Option<DataEntry>>>();
+
+    budget.check(&entries).map_err(warp::reject::custom)?;
+    Ok(MgetResponse {
+        entries,
+        by_address: None,
+    })
+}
+
+#[instrument(skip(page, repo))]
+async fn block_entries_handler(
+    block_uid: i64,
+    page: PageParams,
+    repo: data_entries::Repo,
+    get_params: HashMap<String, String>,
+) -> Result<DataEntriesResponse, Rejection> {
+    page.is_valid().map_err(warp::reject::custom)?;
+    let fields = Fields::from_hashmap(&get_params)?;
+    let max_value_len = max_value_len_from_hashmap(&get_params)?;
+    let include_id = include_id_from_hashmap(&get_params)?;
+    let flat = flat_from_hashmap(&get_params)?;
+
+    let data_entries = repo
+        .entries_by_block_uid(block_uid, page.limit + 1, page.offset, &fields)
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    let has_next_page = data_entries.len() > page.limit as usize;
+    Ok(DataEntriesResponse {
+        entries: data_entries
+            .into_iter()
+            .take(page.limit as usize)
+            .map(|de| {
+                DataEntry::from(de)
+                    .with_id(include_id)
+                    .with_fields(&fields)
+                    .with_max_value_len(max_value_len)
+                    .with_flat(flat)
+            })
+            .collect(),
+        has_next_page,
+        limit: page.limit,
+        offset: page.offset,
+        partial: false,
+    })
+}
+
+#[instrument(skip(page, repo))]
+async fn transaction_entries_handler(
+    transaction_id: String,
+    page: PageParams,
+    repo: data_entries::Repo,
+    get_params: HashMap<String, String>,
+) -> Result<DataEntriesResponse, Rejection> {
+    page.is_valid().map_err(warp::reject::custom)?;
+    let fields = Fields::from_hashmap(&get_params)?;
+    let max_value_len = max_value_len_from_hashmap(&get_params)?;
+    let include_id = include_id_from_hashmap(&get_params)?;
+    let flat = flat_from_hashmap(&get_params)?;
+
+    let data_entries = repo
+        .entries_by_transaction_id(&transaction_id, page.limit + 1, page.offset, &fields)
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    let has_next_page = data_entries.len() > page.limit as usize;
+    Ok(DataEntriesResponse {
+        entries: data_entries
+            .into_iter()
+            .take(page.limit as usize)
+            .map(|de| {
+                DataEntry::from(de)
+                    .with_id(include_id)
+                    .with_fields(&fields)
+                    .with_max_value_len(max_value_len)
+                    .with_flat(flat)
+            })
+            .collect(),
+        has_next_page,
+        limit: page.limit,
+        offset: page.offset,
+        partial: false,
+    })
+}
+
+#[instrument(skip(params, repo))]
+async fn changes_handler(
+    params: ChangesParams,
+    repo: data_entries::Repo,
+    get_params: HashMap<String, String>,
+) -> Result<impl Reply, Rejection> {
+    params.is_valid().map_err(warp::reject::custom)?;
+    // `values=false` leaves only what identifies a change, for consumers invalidating caches
+    let values = !get_params.contains_key("values") || bool_from_hashmap(&get_params, "values")?;
+    let fields = if values {
+        Fields::from_hashmap(&get_params)?
+    } else {
+        Fields::only(&[Field::Address, Field::Key, Field::Height])
+    };
+    let max_value_len = max_value_len_from_hashmap(&get_params)?;
+    let include_id = include_id_from_hashmap(&get_params)?;
+    let flat = flat_from_hashmap(&get_params)?;
+    let include_previous = values && bool_from_hashmap(&get_params, "include_previous")?;
+
+    // the page after an unknown version would be empty rather than the rest of the feed
+    if let Some(cursor) = params.cursor {
+        let known = repo
+            .change_exists(cursor)
+            .await
+            .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+        check_changes_cursor(cursor, known).map_err(warp::reject::custom)?;
+    }
+
+    let data_entries = repo
+        .changes_by_timestamp(
+            params.since_timestamp.timestamp(),
+            params.until_timestamp.map(|t| t.timestamp()),
+            params.cursor,
+            params.limit + 1,
+            &fields,
+        )
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    let mut previous_values = if include_previous {
+        previous_values(&repo, &data_entries).await?
+    } else {
+        HashMap::new()
+    };
+
+    let has_next_page = data_entries.len() > params.limit as usize;
+    let data_entries = data_entries
+        .into_iter()
+        .take(params.limit as usize)
+        .collect::<Vec<_>>();
+    let next_cursor = match data_entries.last() {
+        Some(de) if has_next_page => Some(de.uid),
+        _ => None,
+    };
+
+    Ok(json(&ChangesResponse {
+        entries: data_entries
+            .into_iter()
+            .map(|de| {
+                DataEntry::from(de)
+                    .with_previous_value(&mut previous_values)
+                    .with_id(include_id)
+                    .with_fields(&fields)
+                    .with_max_value_len(max_value_len)
+                    .with_flat(flat)
+            })
+            .collect(),
+        next_cursor,
+        limit: params.limit,
+    }))
+}
+
+fn check_changes_cursor(cursor: i64, known: bool) -> Result<(), AppError> {
+    if known {
+        return Ok(());
+    }
+    Err(AppError::new_validation_error(
+        ValidationErrorCode::InvalidParamenterValue,
+        ErrorDetails {
+            parameter: "cursor".to_string(),
+            reason: format!("unknown cursor {}, pass `next_cursor` of a page.", cursor),
+        },
+    ))
+}
+
+#[instrument(skip(page, repo))]
+async fn changes_at_height_handler(
+    height: i32,
+    page: PageParams,
+    repo: data_entries::Repo,
+    get_params: HashMap<String, String>,
+) -> Result<DataEntriesResponse, Rejection> {
+    if height < 0 {
+        let details = ErrorDetails {
+            parameter: "height".to_string(),
+            reason: format!("height must be between 0 and {}", i32::MAX),
+        };
+        return Err(warp::reject::custom::<AppError>(
+            AppError::new_validation_error(ValidationErrorCode::InvalidParamenterValue, details),
+        ));
+    }
+    page.is_valid().map_err(warp::reject::custom)?;
+    let fields = Fields::from_hashmap(&get_params)?;
+    let max_value_len = max_value_len_from_hashmap(&get_params)?;
+    let include_id = include_id_from_hashmap(&get_params)?;
+    let flat = flat_from_hashmap(&get_params)?;
+    let include_previous = bool_from_hashmap(&get_params, "include_previous")?;
+
+    let data_entries = repo
+        .changes_at_height(height, page.limit + 1, page.offset, &fields)
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    let mut previous_values = if include_previous {
+        previous_values(&repo, &data_entries).await?
+    } else {
+        HashMap::new()
+    };
+
+    let has_next_page = data_entries.len() > page.limit as usize;
+    Ok(DataEntriesResponse {
+        entries: data_entries
+            .into_iter()
+            .take(page.limit as usize)
+            .map(|de| {
+                DataEntry::from(de)
+                    .with_previous_value(&mut previous_values)
+                    .with_id(include_id)
+                    .with_fields(&fields)
+                    .with_max_value_len(max_value_len)
+                    .with_flat(flat)
+            })
+            .collect(),
+        has_next_page,
+        limit: page.limit,
+        offset: page.offset,
+        partial: false,
+    })
+}
+
+#[instrument(skip(params, repo))]
+async fn key_history_handler(
+    address: String,
+    key: String,
+    params: KeyHistoryParams,
+    repo: data_entries::Repo,
+    get_params: HashMap<String, String>,
+) -> Result<impl Reply, Rejection> {
+    params.is_valid().map_err(warp::reject::custom)?;
+    let key = KeyEncoding::from_hashmap(&get_params)?.decode(decode_uri_string(key)?)?;
+    let fields = Fields::from_hashmap(&get_params)?;
+    let max_value_len = max_value_len_from_hashmap(&get_params)?;
+    let include_id = include_id_from_hashmap(&get_params)?;
+    let flat = flat_from_hashmap(&get_params)?;
+
+    let data_entries = repo
+        .key_history(&address, &key, params.cursor, params.limit + 1, &fields)
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    let has_next_page = data_entries.len() > params.limit as usize;
+    let data_entries = data_entries
+        .into_iter()
+        .take(params.limit as usize)
+        .collect::<Vec<_>>();
+    let next_cursor = match data_entries.last() {
+        Some(de) if has_next_page => Some(de.uid),
+        _ => None,
+    };
+
+    Ok(json(&KeyHistoryResponse {
+        entries: data_entries
+            .into_iter()
+            .map(|de| {
+                DataEntry::from(de)
+                    .with_id(include_id)
+                    .with_fields(&fields)
+                    .with_max_value_len(max_value_len)
+                    .with_flat(flat)
+            })
+            .collect(),
+        next_cursor,
+        limit: params.limit,
+    }))
+}
+
+#[instrument(skip(repo))]
+async fn value_ever_handler(
+    address: String,
+    key: String,
+    params: ValueEverParams,
+    repo: data_entries::Repo,
+    get_params: HashMap<String, String>,
+) -> Result<impl Reply, Rejection> {
+    params.is_valid().map_err(warp::reject::custom)?;
+    let key = KeyEncoding::from_hashmap(&get_params)?.decode(decode_uri_string(key)?)?;
+    let filter = params.value_filter().map_err(warp::reject::custom)?;
+
+    let heights = repo
+        .value_ever_equaled(
+            &address,
+            &key,
+            filter,
+            params.from_height,
+            params.to_height,
+            params.limit + 1,
+        )
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    let has_next_page = heights.len() > params.limit as usize;
+    Ok(json(&HeightsResponse {
+        heights: heights.into_iter().take(params.limit as usize).collect(),
+        has_next_page,
+        limit: params.limit,
+    }))
+}
+
+#[instrument(skip(params, repo))]
+async fn grouped_handler(
+    address: String,
+    params: GroupedParams,
+    repo: data_entries::Repo,
+    get_params: HashMap<String, String>,
+) -> Result<impl Reply, Rejection> {
+    params.is_valid().map_err(warp::reject::custom)?;
+    let fields = Fields::from_hashmap(&get_params)?;
+    let max_value_len = max_value_len_from_hashmap(&get_params)?;
+    let include_id = include_id_from_hashmap(&get_params)?;
+    let flat = flat_from_hashmap(&get_params)?;
+
+    let entries = repo
+        .grouped_latest(&address, &params.by, &params.order, params.limit, &fields)
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?
+        .into_iter()
+        .map(|de| {
+            DataEntry::from(de)
+                .with_id(include_id)
+                .with_fields(&fields)
+                .with_max_value_len(max_value_len)
+                .with_flat(flat)
         })
+        .collect();
+
+    Ok(json(&GroupedResponse { entries }))
 }
 
+/// Latest values of an address by their keys, `{"key": value}`.
 #[instrument(skip(repo))]
+async fn address_map_handler(
+    address: String,
+    params: MapParams,
+    repo: data_entries::Repo,
+    get_params: HashMap<String, String>,
+) -> Result<impl Reply, Rejection> {
+    params.is_valid().map_err(warp::reject::custom)?;
+    let max_value_len = max_value_len_from_hashmap(&get_params)?;
+    let typed = typed_from_hashmap(&get_params)?;
+
+    reject_if_behind_min_height(&get_params, &repo).await?;
+
+    let mut filters = vec![RequestFilter::Address(AddressFilter { value: address })];
+    if let Some(prefix) = params.prefix {
+        filters.push(RequestFilter::Key(KeyFilter {
+            value: prefix,
+            operation: KeyOperation::Prefix,
+        }));
+    }
+    let sort = RequestSort(vec![SortItem::Key {
+        direction: SortItemDirection::Asc,
+    }]);
+
+    let data_entries = repo
+        .search_data_entries(
+            Some(RequestFilter::And(AndFilter(filters))),
+            Some(sort),
+            None,
+            params.limit,
+            0,
+            &Fields::only(&[Field::Key, Field::Value]),
+        )
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    // a key has a single latest entry, should it have more, the most recent one is kept
+    let mut latest: BTreeMap<String, (i64, DataEntryType)> = BTreeMap::new();
+    for de in data_entries {
+        let entry = DataEntry::from(de).with_max_value_len(max_value_len);
+        if let (Some(key), Some(value)) = (entry.key, entry.value) {
+            match latest.get(&key) {
+                Some((uid, _)) if *uid > entry.uid => {}
+                _ => {
+                    latest.insert(key, (entry.uid, value));
+                }
+            }
+        }
+    }
+
+    let values = latest
+        .into_iter()
+        .map(|(key, (_, value))| (key, value))
+        .collect::<BTreeMap<_, _>>();
+    if typed {
+        let values = values
+            .iter()
+            .map(|(key, value)| (key, TypedValue(value)))
+            .collect::<BTreeMap<_, _>>();
+        Ok(json(&values))
+    } else {
+        Ok(json(&values))
+    }
+}
+
+#[instrument(skip(repo, coalescer))]
 async fn get_by_address_key_handler(
     address: String,
     key: String,
     repo: data_entries::Repo,
+    coalescer: Coalescer,
     get_params: HashMap<String, String>,
 ) -> Result<DataEntry, Rejection> {
     let hp = HistoricalRequestParams::from_hashmap(&get_params)?;
+    let fields = Fields::from_hashmap(&get_params)?;
+    let typed = typed_from_hashmap(&get_params)?;
+    let max_value_len = max_value_len_from_hashmap(&get_params)?;
+    let include_id = include_id_from_hashmap(&get_params)?;
+    let flat = flat_from_hashmap(&get_params)?;
 
-    let key = decode_uri_string(key)?;
-    let entry = Entry {
-        address: address.clone(),
-        key: key.clone(),
-    };
+    let key = KeyEncoding::from_hashmap(&get_params)?.decode(decode_uri_string(key)?)?;
 
-    let mget_entries = MgetEntries {
-        address_key_pairs: vec![entry],
+    let data_entries = if hp.is_empty() {
+        reject_if_behind_min_height(&get_params, &repo).await?;
+        coalescer
+            .latest_data_entries(repo.clone(), address, key)
+            .await
+    } else {
+        let entry = Entry {
+            address: address.clone(),
+            key: key.clone(),
+        };
+
+        let mget_entries = MgetEntries {
+            address_key_pairs: vec![entry],
+            filter: None,
+        };
+
+        reject_if_before_indexed(&hp, &repo).await?;
+
+        let e_uids = repo
+            .find_entities_uids(&hp, &mget_entries)
+            .await
+            .or_else::<Rejection, _>(|err| {
+                Err(warp::reject::custom::<AppError>(AppError::from(err).into()).into())
+            })?;
+
+        reject_if_empty_uids(&hp, &e_uids)?;
+
+        repo.mget_data_entries(mget_entries, &e_uids, &Fields::default())
+            .await
+            .map_err(AppError::from)
     };
 
-    let e_uids = repo
-        .find_entities_uids(&hp, &mget_entries)
+    let data_entries = data_entries.map_err(warp::reject::custom)?;
+    let version_counts = version_counts(&repo, &data_entries, &get_params).await?;
+    let first_heights = first_heights(&repo, &data_entries, &get_params).await?;
+
+    if let Some(de) = data_entries.first() {
+        Ok(DataEntry::from(de.clone())
+            .with_version_count(&version_counts)
+            .with_first_height(&first_heights)
+            .with_id(include_id)
+            .with_fields(&fields)
+            .with_max_value_len(max_value_len)
+            .with_flat(flat)
+            .with_typed_value(typed))
+    } else {
+        Err(warp::reject::not_found())
+    }
+}
+
+/// Version counts of the entries by address and key, empty unless `include_version_count=true`.
+async fn version_counts(
+    repo: &data_entries::Repo,
+    data_entries: &[data_entries::DataEntry],
+    get_params: &HashMap<String, String>,
+) -> Result<HashMap<(String, String), i64>, Rejection> {
+    if !bool_from_hashmap(get_params, "include_version_count")? {
+        return Ok(HashMap::new());
+    }
+
+    let address_key_pairs = data_entries
+        .iter()
+        .map(|de| (de.address.clone(), de.key.clone()))
+        .collect::<Vec<_>>();
+    let version_counts = repo
+        .version_counts(&address_key_pairs)
         .await
-        .or_else::<Rejection, _>(|err| {
-            Err(warp::reject::custom::<AppError>(AppError::DbError(err.to_string()).into()).into())
-        })?;
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
 
-    reject_if_empty_uids(&hp, &e_uids)?;
+    Ok(version_counts
+        .into_iter()
+        .map(|v| ((v.address, v.key), v.version_count))
+        .collect())
+}
 
-    repo.mget_data_entries(mget_entries, build_historical_sql(&e_uids))
+/// First heights of the entries by address and key, empty unless `include_first_height=true`.
+async fn first_heights(
+    repo: &data_entries::Repo,
+    data_entries: &[data_entries::DataEntry],
+    get_params: &HashMap<String, String>,
+) -> Result<HashMap<(String, String), i32>, Rejection> {
+    if !bool_from_hashmap(get_params, "include_first_height")? {
+        return Ok(HashMap::new());
+    }
+
+    let address_key_pairs = data_entries
+        .iter()
+        .map(|de| (de.address.clone(), de.key.clone()))
+        .collect::<Vec<_>>();
+    let first_heights = repo
+        .first_heights(&address_key_pairs)
         .await
-        .or_else::<Rejection, _>(|err| {
-            Err(warp::reject::custom::<AppError>(AppError::DbError(err.to_string()).into()).into())
-        })
-        .and_then(|data_entries| {
-            if let Some(de) = data_entries.first() {
-                Ok(DataEntry::from(de.clone()))
-            } else {
-                Err(warp::reject::not_found())
-            }
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    Ok(first_heights
+        .into_iter()
+        .map(|f| ((f.address, f.key), f.first_height))
+        .collect())
+}
+
+/// Values replaced by the entry versions, by the uid of the replacing version.
+async fn previous_values(
+    repo: &data_entries::Repo,
+    data_entries: &[data_entries::DataEntry],
+) -> Result<HashMap<i64, DataEntryType>, Rejection> {
+    let uids = data_entries.iter().map(|de| de.uid).collect::<Vec<_>>();
+    let previous_values = repo
+        .previous_values(&uids)
+        .await
+        .map_err(|err| warp::reject::custom(AppError::from(err)))?;
+
+    Ok(previous_values
+        .into_iter()
+        .filter_map(|p| {
+            entry_value(
+                p.value_binary,
+                p.value_bool,
+                p.value_integer,
+                p.value_string,
+            )
+            .map(|value| (p.superseded_by, value))
         })
+        .collect())
+}
+
+async fn reject_if_before_indexed(
+    hp: &HistoricalRequestParams,
+    repo: &data_entries::Repo,
+) -> Result<(), Rejection> {
+    let height = match hp.height {
+        Some(height) => height,
+        None => return Ok(()),
+    };
+
+    let earliest_height = repo
+        .get_earliest_indexed_height()
+        .await
+        .map_err(|err| warp::reject::custom::<AppError>(AppError::from(err)))?;
+
+    match earliest_height {
+        Some(earliest_height) if height < earliest_height as i64 => {
+            let details = ErrorDetails {
+                parameter: "height".to_string(),
+                reason: format!(
+                    "height {} is out of the indexed range, indexed heights start at {}.",
+                    height, earliest_height
+                ),
+            };
+            Err(warp::reject::custom::<AppError>(
+                AppError::new_validation_error(
+                    ValidationErrorCode::InvalidParamenterValue,
+                    details,
+                ),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+// min_height lets clients avoid reading the latest state before
+// the indexer has caught up with their own writes
+async fn reject_if_behind_min_height(
+    get_params: &HashMap<String, String>,
+    repo: &data_entries::Repo,
+) -> Result<(), Rejection> {
+    let min_height = match get_params.get("min_height") {
+        Some(min_height) => min_height.parse::<i32>().map_err(|_| {
+            let details = ErrorDetails {
+                parameter: "min_height".to_string(),
+                reason: "min_height must be an integer.".to_string(),
+            };
+            warp::reject::custom::<AppError>(AppError::new_validation_error(
+                ValidationErrorCode::InvalidParamenterValue,
+                details,
+            ))
+        })?,
+        None => return Ok(()),
+    };
+
+    let last_height = repo
+        .get_last_indexed_height()
+        .await
+        .map_err(|err| warp::reject::custom::<AppError>(AppError::from(err)))?
+        .unwrap_or(0);
+
+    check_indexed_height(min_height, last_height).map_err(warp::reject::custom)
+}
+
+fn check_indexed_height(min_height: i32, last_height: i32) -> Result<(), AppError> {
+    if last_height < min_height {
+        Err(AppError::IndexerBehind(last_height))
+    } else {
+        Ok(())
+    }
+}
+
+fn cache_flush_handler(coalescer: Coalescer) -> Response {
+    coalescer.clear();
+    info!("in-flight lookups cache flushed");
+    warp::reply::with_status(warp::reply(), StatusCode::NO_CONTENT).into_response()
+}
+
+/// Pins a request for the latest state to the last indexed height with `snapshot=true`,
+/// so that all of its keys are read as of the same block.
+async fn with_snapshot_height(
+    hp: HistoricalRequestParams,
+    get_params: &HashMap<String, String>,
+    repo: &data_entries::Repo,
+) -> Result<HistoricalRequestParams, Rejection> {
+    if !hp.is_empty() || !bool_from_hashmap(get_params, "snapshot")? {
+        return Ok(hp);
+    }
+
+    let last_height = repo
+        .get_last_indexed_height()
+        .await
+        .map_err(|err| warp::reject::custom::<AppError>(AppError::from(err)))?;
+
+    Ok(HistoricalRequestParams {
+        height: last_height.map(i64::from),
+        ..hp
+    })
 }
 
 fn reject_if_empty_uids(hp: &HistoricalRequestParams, uids: &Vec<i64>) -> Result<(), Rejection> {
@@ -559,10 +2454,255 @@ fn reject_if_empty_uids(hp: &HistoricalRequestParams, uids: &Vec<i64>) -> Result
     Ok(())
 }
 
-fn build_historical_sql(uids: &Vec<i64>) -> String {
-    if uids.is_empty() {
-        " AND de.superseded_by = $1".to_string()
-    } else {
-        format!(" AND de.uid in ({}) AND $1 = $1", uids.iter().join(","))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string_entry(value: &str) -> DataEntry {
+        DataEntry::from(data_entries::DataEntry {
+            address: "3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP".into(),
+            key: "name".into(),
+            value_string: Some(value.into()),
+            ..Default::default()
+        })
+    }
+
+    fn entry_json(params: &HashMap<String, String>) -> serde_json::Value {
+        let entry = DataEntry::from(data_entries::DataEntry {
+            address: "3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP".into(),
+            key: "price_7".into(),
+            fragment_0_string: Some("price".into()),
+            fragment_1_integer: Some(7),
+            ..Default::default()
+        });
+        let fields = Fields::from_hashmap(params).unwrap();
+        serde_json::to_value(entry.with_fields(&fields)).unwrap()
+    }
+
+    #[test]
+    fn fragments_only_with_flag() {
+        let json = entry_json(&HashMap::new());
+        assert!(json.get("fragments").is_none());
+
+        let mut params = HashMap::new();
+        params.insert("include_fragments".to_string(), "true".to_string());
+        let json = entry_json(&params);
+        assert_eq!(
+            json["fragments"]["key"],
+            serde_json::json!([
+                {"type": "string", "value": "price"},
+                {"type": "integer", "value": 7},
+            ])
+        );
+    }
+
+    #[test]
+    fn id_is_stable_per_version() {
+        let id = |uid| {
+            DataEntry::from(data_entries::DataEntry {
+                uid,
+                address: "3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP".into(),
+                key: "name".into(),
+                value_string: Some("alice".into()),
+                ..Default::default()
+            })
+            .with_id(true)
+            .id
+            .unwrap()
+        };
+
+        assert_eq!(id(7), id(7));
+        assert_ne!(id(7), id(8));
+        assert_eq!(
+            id(7),
+            format!(
+                "{:x}",
+                md5::compute("3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP:7:name")
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn csv_and_protobuf_imply_fragments() {
+        for accept in &["text/csv", "application/x-protobuf"] {
+            let params = warp::test::request()
+                .path("/search?limit=1")
+                .header("accept", *accept)
+                .filter(&format_query_params())
+                .await
+                .unwrap();
+            assert_eq!(
+                params.get("include_fragments").map(String::as_str),
+                Some("true")
+            );
+        }
+
+        let params = warp::test::request()
+            .path("/search?limit=1")
+            .header("accept", "application/json")
+            .filter(&format_query_params())
+            .await
+            .unwrap();
+        assert!(!params.contains_key("include_fragments"));
+    }
+
+    #[tokio::test]
+    async fn exceeding_rate_is_rejected() {
+        let filter = rate_limit(Some(RateLimiter::new(1, 2)));
+        let request = |api_key: &str| {
+            warp::test::request()
+                .path("/search")
+                .header("x-api-key", api_key)
+        };
+
+        for _ in 0..2 {
+            assert!(request("first").filter(&filter).await.is_ok());
+        }
+        let rej = request("first").filter(&filter).await.unwrap_err();
+        assert!(matches!(
+            rej.find::<AppError>(),
+            Some(AppError::RateLimited(1))
+        ));
+
+        // other clients have buckets of their own
+        assert!(request("second").filter(&filter).await.is_ok());
+    }
+
+    #[test]
+    fn validation_errors_are_counted() {
+        let counter = || {
+            metrics::VALIDATION_ERRORS
+                .with_label_values(&["limit", "950201"])
+                .get()
+        };
+        let before = counter();
+        let err = PageParams {
+            limit: parsing::LIMIT_MAX + 1,
+            offset: 0,
+        }
+        .is_valid()
+        .unwrap_err();
+
+        app_error_response(&err);
+
+        assert_eq!(counter(), before + 1);
+    }
+
+    #[tokio::test]
+    async fn explain_requests_skip_search() {
+        let body = || warp::body::json::<serde_json::Value>();
+        let routes = without_explain()
+            .and(body())
+            .map(|_| "rows")
+            .or(explain_analyze().and(body()).map(|_| "plan"))
+            .unify();
+        let request = |path: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(path)
+                .json(&serde_json::json!({ "limit": 1 }))
+        };
+
+        let res = request("/search?explain=analyze").filter(&routes).await;
+        assert_eq!(res.unwrap(), "plan");
+        let res = request("/search").filter(&routes).await;
+        assert_eq!(res.unwrap(), "rows");
+    }
+
+    #[tokio::test]
+    async fn indents_json_only_with_flag() {
+        let value = serde_json::json!({
+            "entries": [{"key": "a {\"b\": [1]}", "value": 1}, null],
+            "empty": {},
+        });
+        let body = |pretty: bool| {
+            let value = value.clone();
+            async move {
+                let res = prettified(pretty, json(&value)).await.unwrap();
+                let body = warp::hyper::body::to_bytes(res.into_body()).await.unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+
+        assert_eq!(
+            body(true).await,
+            serde_json::to_string_pretty(&value).unwrap()
+        );
+        assert_eq!(body(false).await, serde_json::to_string(&value).unwrap());
+
+        // fields keep the order they are serialized in
+        let res = prettified(
+            true,
+            json(&ChangesResponse {
+                entries: vec![],
+                next_cursor: Some(7),
+                limit: 1,
+            }),
+        )
+        .await
+        .unwrap();
+        let body = warp::hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(
+            body,
+            "{\n  \"entries\": [],\n  \"next_cursor\": 7,\n  \"limit\": 1\n}"
+        );
+    }
+
+    #[test]
+    fn min_height_waits_for_indexer() {
+        assert!(matches!(
+            check_indexed_height(101, 100),
+            Err(AppError::IndexerBehind(100))
+        ));
+        assert!(check_indexed_height(100, 100).is_ok());
+        assert!(check_indexed_height(100, 101).is_ok());
+    }
+
+    #[test]
+    fn unknown_changes_cursor_is_rejected() {
+        assert!(check_changes_cursor(42, true).is_ok());
+        match check_changes_cursor(42, false) {
+            Err(AppError::ValidationError(_, code, Some(details))) => {
+                assert_eq!(code, ValidationErrorCode::InvalidParamenterValue as u32);
+                assert_eq!(details.parameter, "cursor");
+            }
+            err => panic!("unexpected result {:?}", err),
+        }
+    }
+
+    #[test]
+    fn missing_block_height_is_null() {
+        let entry = DataEntry::from(data_entries::DataEntry {
+            address: "3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP".into(),
+            key: "name".into(),
+            height: None,
+            ..Default::default()
+        });
+
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json.get("height"), Some(&serde_json::Value::Null));
+
+        let projected = entry.with_fields(&Fields::only(&[Field::Address, Field::Key]));
+        let json = serde_json::to_value(&projected).unwrap();
+        assert_eq!(json.get("height"), None);
+    }
+
+    #[test]
+    fn truncates_at_char_boundary() {
+        // 2 bytes per char, the limit falls inside the second one
+        let entry = string_entry("ключ").with_max_value_len(Some(3));
+
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["value"], "к");
+        assert_eq!(json["truncated"], true);
+    }
+
+    #[test]
+    fn keeps_values_within_max_len() {
+        let entry = string_entry("ключ").with_max_value_len(Some(8));
+
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["value"], "ключ");
+        assert!(json.get("truncated").is_none());
     }
 }
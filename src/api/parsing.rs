@@ -1,7 +1,19 @@
 use super::errors::{AppError, ErrorDetails, ValidationErrorCode};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
 use serde::Deserialize;
 
-const LIMIT_MAX: u64 = 5000;
+pub const LIMIT_MAX: u64 = 5000;
+pub const FRAGMENT_POSITION_MAX: u64 = 10;
+// nesting levels a `value_json` path may go into
+pub const VALUE_JSON_DEPTH_MAX: usize = 8;
+// prefixes of a single `key_prefix_in` filter
+pub const KEY_PREFIXES_MAX: usize = 50;
+// decimal numbers stored as strings, compared as `numeric` by `bignum` values
+pub const BIGNUM_PATTERN: &str = r"^-?[0-9]+(\.[0-9]+)?$";
+
+static BIGNUM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(BIGNUM_PATTERN).unwrap());
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -12,11 +24,278 @@ pub struct SearchRequest {
     pub limit: u64,
     #[serde(default = "default_offset")]
     pub offset: u64,
+    pub dedup_by: Option<DedupBy>,
+    // entries of the page follow the order of the `keys` filter
+    #[serde(default)]
+    pub preserve_input_order: bool,
+}
+
+/// Simple search passed as query params of `GET /search`, the predicates are and-ed.
+/// `sort` is a comma-separated list of `key`, `address` or `base`, `-` prefixed for descending.
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub address: Option<String>,
+    pub key_prefix: Option<String>,
+    pub value_integer_gte: Option<i64>,
+    pub sort: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: u64,
+    #[serde(default = "default_offset")]
+    pub offset: u64,
+}
+
+impl SearchQuery {
+    pub fn into_search_request(self) -> Result<SearchRequest, AppError> {
+        let mut filters = vec![];
+        if let Some(address) = self.address {
+            filters.push(RequestFilter::Address(AddressFilter { value: address }));
+        }
+        if let Some(key_prefix) = self.key_prefix {
+            filters.push(RequestFilter::Key(KeyFilter {
+                value: key_prefix,
+                operation: KeyOperation::Prefix,
+            }));
+        }
+        if let Some(value) = self.value_integer_gte {
+            filters.push(RequestFilter::Value(ValueFilter {
+                value_type: ValueType::Integer,
+                operation: Operation::Gte,
+                value: ValueData::Integer(value),
+                coerce_string: false,
+            }));
+        }
+        let filter = match filters.len() {
+            0 => None,
+            1 => filters.pop(),
+            _ => Some(RequestFilter::And(AndFilter(filters))),
+        };
+
+        let sort = self
+            .sort
+            .map(|sort| {
+                sort.split(',')
+                    .map(|item| {
+                        let (name, direction) = match item.trim().strip_prefix('-') {
+                            Some(name) => (name, SortItemDirection::Desc),
+                            None => (item.trim(), SortItemDirection::Asc),
+                        };
+                        match name {
+                            "key" => Ok(SortItem::Key { direction }),
+                            "address" => Ok(SortItem::Address { direction }),
+                            "base" => Ok(SortItem::Base { direction }),
+                            _ => Err(app_error(
+                                "sort".into(),
+                                format!(
+                                    "unknown sort `{}`, expected one of: key, address, base.",
+                                    name
+                                ),
+                            )),
+                        }
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(RequestSort)
+            })
+            .transpose()?;
+
+        let req = SearchRequest {
+            filter,
+            sort,
+            limit: self.limit,
+            offset: self.offset,
+            dedup_by: None,
+            preserve_input_order: false,
+        };
+        req.is_valid()?;
+        Ok(req)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AddressesSearchRequest {
+    pub filter: Option<RequestFilter>,
+    #[serde(default = "default_limit")]
+    pub limit: u64,
+    #[serde(default = "default_offset")]
+    pub offset: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AddressesCountRequest {
+    pub filter: Option<RequestFilter>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    #[serde(default = "default_limit")]
+    pub limit: u64,
+    #[serde(default = "default_offset")]
+    pub offset: u64,
+}
+
+/// Page of a key history, `limit` is required, since hot keys have many versions.
+#[derive(Debug, Deserialize)]
+pub struct KeyHistoryParams {
+    pub limit: u64,
+    // `next_cursor` of the previous page
+    pub cursor: Option<i64>,
+}
+
+impl KeyHistoryParams {
+    pub fn is_valid(&self) -> Result<(), AppError> {
+        valid_limit(self.limit)
+    }
+}
+
+/// Window of the change feed, `until_timestamp` is exclusive.
+#[derive(Debug, Deserialize)]
+pub struct ChangesParams {
+    pub since_timestamp: DateTime<Utc>,
+    pub until_timestamp: Option<DateTime<Utc>>,
+    #[serde(default = "default_limit")]
+    pub limit: u64,
+    // `next_cursor` of the previous page
+    pub cursor: Option<i64>,
+}
+
+impl ChangesParams {
+    pub fn is_valid(&self) -> Result<(), AppError> {
+        valid_limit(self.limit)?;
+        if let Some(until_timestamp) = self.until_timestamp {
+            if until_timestamp <= self.since_timestamp {
+                return Err(app_error(
+                    "until_timestamp".into(),
+                    "until_timestamp must be after since_timestamp".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GroupedParams {
+    pub by: GroupBy,
+    #[serde(default)]
+    pub order: GroupOrder,
+    pub limit: Option<u64>,
+}
+
+impl GroupedParams {
+    pub fn is_valid(&self) -> Result<(), AppError> {
+        self.limit.map_or(Ok(()), valid_limit)
+    }
+}
+
+/// Query of the heights at which a key was set to the value, parsed according to `type`.
+#[derive(Debug, Deserialize)]
+pub struct ValueEverParams {
+    #[serde(rename = "type")]
+    pub value_type: ValueType,
+    pub value: String,
+    pub from_height: Option<i32>,
+    pub to_height: Option<i32>,
+    #[serde(default = "default_limit")]
+    pub limit: u64,
+}
+
+impl ValueEverParams {
+    pub fn is_valid(&self) -> Result<(), AppError> {
+        valid_limit(self.limit)?;
+        if let (Some(from_height), Some(to_height)) = (self.from_height, self.to_height) {
+            if from_height > to_height {
+                return Err(app_error(
+                    "from_height".into(),
+                    "from_height must not be greater than to_height.".into(),
+                ));
+            }
+        }
+        self.value_filter().map(|_| ())
+    }
+
+    pub fn value_filter(&self) -> Result<ValueFilter, AppError> {
+        let type_error = || {
+            let value_type = self.value_type.to_type();
+            app_error(
+                "value".into(),
+                format!(
+                    "`{}` value type requires `value` of {} type.",
+                    value_type, value_type
+                ),
+            )
+        };
+        let value = match self.value_type {
+            ValueType::String => ValueData::String(self.value.clone()),
+            ValueType::Integer => ValueData::Integer(self.value.parse().map_err(|_| type_error())?),
+            ValueType::Bool => ValueData::Bool(self.value.parse().map_err(|_| type_error())?),
+            ValueType::Binary => ValueData::Binary(
+                decode_binary(&self.value).map_err(|reason| app_error("value".into(), reason))?,
+            ),
+            ValueType::Bignum => {
+                valid_bignum(&self.value).map_err(|reason| app_error("value".into(), reason))?;
+                ValueData::String(self.value.clone())
+            }
+        };
+        Ok(ValueFilter {
+            value_type: self.value_type.clone(),
+            operation: Operation::Eq,
+            value,
+            coerce_string: false,
+        })
+    }
+}
+
+/// Query of the key to value map of an address, keys may be narrowed down by `prefix`.
+#[derive(Debug, Deserialize)]
+pub struct MapParams {
+    pub prefix: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: u64,
+}
+
+impl MapParams {
+    pub fn is_valid(&self) -> Result<(), AppError> {
+        valid_limit(self.limit)
+    }
+}
+
+/// Order of the groups, `value_*` orders by the integer value of the group latest entry,
+/// so that `order=value_desc&limit=N` gives the top N groups.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupOrder {
+    Group,
+    ValueAsc,
+    ValueDesc,
+}
+
+impl Default for GroupOrder {
+    fn default() -> Self {
+        GroupOrder::Group
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum GroupBy {
+    #[serde(rename = "fragment_0")]
+    Fragment0,
+}
+
+/// Keeps a single entry, the one with the highest uid, per key or per address and key.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupBy {
+    Key,
+    AddressKey,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct MgetEntries {
     pub address_key_pairs: Vec<Entry>,
+    // applied to the resolved entries, latest or at the requested height
+    #[serde(default)]
+    pub filter: Option<RequestFilter>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -39,18 +318,74 @@ impl MgetEntries {
                 key,
             })
             .collect();
-        Self { address_key_pairs }
+        Self {
+            address_key_pairs,
+            filter: None,
+        }
+    }
+
+    pub fn is_valid(&self) -> Result<(), AppError> {
+        self.filter
+            .as_ref()
+            .map(|f| f.is_valid("filter.".to_string()))
+            .unwrap_or(Ok(()))
     }
 }
 
 impl SearchRequest {
     pub fn is_valid(&self) -> Result<(), AppError> {
-        if self.limit > LIMIT_MAX {
+        valid_limit(self.limit)?;
+        if let Some(sort) = &self.sort {
+            sort.is_valid()?;
+        }
+        if self.sort.as_ref().map_or(false, |s| s.has_relevance()) && self.key_prefix().is_none() {
             return Err(app_error(
-                "limit".into(),
-                format!("maximum value {} exceeded", LIMIT_MAX),
+                "sort".into(),
+                "`relevance` sort requires a `key` filter with `prefix` operation.".into(),
             ));
         }
+        if self.preserve_input_order {
+            if self.input_keys().is_none() {
+                return Err(app_error(
+                    "preserve_input_order".into(),
+                    "`preserve_input_order` requires a `keys` filter.".into(),
+                ));
+            }
+            if self.sort.is_some() {
+                return Err(AppError::new_validation_error(
+                    ValidationErrorCode::ConflictingParameters,
+                    ErrorDetails {
+                        parameter: "preserve_input_order, sort".into(),
+                        reason: "`preserve_input_order` can't be used with `sort`.".into(),
+                    },
+                ));
+            }
+        }
+        self.filter
+            .as_ref()
+            .map(|f| f.is_valid("filter.".to_string()))
+            .unwrap_or(Ok(()))
+    }
+}
+
+impl PageParams {
+    pub fn is_valid(&self) -> Result<(), AppError> {
+        valid_limit(self.limit)
+    }
+}
+
+impl AddressesCountRequest {
+    pub fn is_valid(&self) -> Result<(), AppError> {
+        self.filter
+            .as_ref()
+            .map(|f| f.is_valid("filter.".to_string()))
+            .unwrap_or(Ok(()))
+    }
+}
+
+impl AddressesSearchRequest {
+    pub fn is_valid(&self) -> Result<(), AppError> {
+        valid_limit(self.limit)?;
         self.filter
             .as_ref()
             .map(|f| f.is_valid("filter.".to_string()))
@@ -58,6 +393,39 @@ impl SearchRequest {
     }
 }
 
+impl SearchRequest {
+    /// Prefix of the key prefix filter, at the top level or in a top-level `and`.
+    pub fn key_prefix(&self) -> Option<String> {
+        let is_prefix = |f: &RequestFilter| match f {
+            RequestFilter::Key(KeyFilter {
+                value,
+                operation: KeyOperation::Prefix,
+            }) => Some(value.clone()),
+            _ => None,
+        };
+        match &self.filter {
+            Some(RequestFilter::And(AndFilter(filters))) => filters.iter().find_map(is_prefix),
+            Some(filter) => is_prefix(filter),
+            None => None,
+        }
+    }
+
+    /// Keys of the `keys` filter, at the top level or in a top-level `and`.
+    pub fn input_keys(&self) -> Option<&[String]> {
+        fn is_keys(f: &RequestFilter) -> Option<&[String]> {
+            match f {
+                RequestFilter::Keys(KeysFilter(keys)) => Some(keys.as_slice()),
+                _ => None,
+            }
+        }
+        match &self.filter {
+            Some(RequestFilter::And(AndFilter(filters))) => filters.iter().find_map(is_keys),
+            Some(filter) => is_keys(filter),
+            None => None,
+        }
+    }
+}
+
 impl RequestFilter {
     fn is_valid(&self, context: String) -> Result<(), AppError> {
         match self {
@@ -65,9 +433,14 @@ impl RequestFilter {
             RequestFilter::Or(filter) => filter.is_valid(context),
             RequestFilter::In(filter) => filter.is_valid(context),
             RequestFilter::Fragment(filter) => filter.is_valid(context),
+            RequestFilter::FragmentAny(filter) => filter.is_valid(context),
             RequestFilter::ValueFragment(filter) => filter.is_valid(context),
             RequestFilter::Key(filter) => filter.is_valid(context),
+            RequestFilter::Keys(filter) => filter.is_valid(context),
+            RequestFilter::KeyPrefixIn(filter) => filter.is_valid(context),
+            RequestFilter::ValueJson(filter) => filter.is_valid(context),
             RequestFilter::Value(filter) => filter.is_valid(context),
+            RequestFilter::ValueSize(filter) => filter.is_valid(context),
             RequestFilter::Address(filter) => filter.is_valid(context),
         }
     }
@@ -93,6 +466,17 @@ impl OrFilter {
 
 impl InFilter {
     fn is_valid(&self, context: String) -> Result<(), AppError> {
+        // a repeated property would make a malformed `(key, key) IN (...)` tuple
+        for (index, property) in self.properties.iter().enumerate() {
+            if self.properties[..index].contains(property) {
+                let reason = format!("{} is repeated in `properties`.", property.to_name());
+                return Err(app_error(
+                    format!("{}in.properties[{}]", context, index),
+                    reason,
+                ));
+            }
+        }
+
         self.values.iter().try_fold(0, |idx, row| {
             if row.len() != self.properties.len() {
                 let reason = format!("`values` row length at index {} is {}, while it should be equal to `properties` count ({}).", idx, row.len(), self.properties.len());
@@ -108,9 +492,18 @@ impl InFilter {
                         let context = format!("{}in[{}][{}]", context, idx, index);
                         item.is_valid(context)?;
                     }
+                    (item @ InItemFilter::Fragment { .. }, InFilterValue::Null) => {
+                        let context = format!("{}in[{}][{}]", context, idx, index);
+                        item.is_valid(context)?;
+                    }
+                    (InItemFilter::Value { .. }, InFilterValue::Null) => {}
                     (InItemFilter::Key {  }, InFilterValue::StringVal(_)) => {}
                     (InItemFilter::Address {  }, InFilterValue::StringVal(_)) => {}
                     (InItemFilter::Value { value_type: ValueType::Binary }, InFilterValue::BinaryVal(_)) => {}
+                    (InItemFilter::Value { value_type: ValueType::Binary }, InFilterValue::StringVal(s)) => {
+                        let parameter = format!("{}in.values[{}][{}]", context, idx, index);
+                        decode_binary(s).map_err(|reason| app_error(parameter, reason))?;
+                    }
                     (InItemFilter::Value { value_type: ValueType::Bool }, InFilterValue::BoolVal(_)) => {}
                     (InItemFilter::Value { value_type: ValueType::Integer }, InFilterValue::IntVal(_)) => {}
                     (InItemFilter::Value { value_type: ValueType::String }, InFilterValue::StringVal(_)) => {}
@@ -155,6 +548,9 @@ fn in_item_filter_error(
 impl KeyFragmentFilter {
     fn is_valid(&self, context: String) -> Result<(), AppError> {
         let new_context = format!("{}fragment", context);
+        if self.position > FRAGMENT_POSITION_MAX {
+            return Err(app_error(new_context, position_out_of_range()));
+        }
         match self {
             Self {
                 value: FragmentValueType::IntVal(_),
@@ -173,27 +569,55 @@ impl KeyFragmentFilter {
                 "`integer` fragment type requires `value` of integer type, found string.".into(),
             )),
             Self {
-                fragment_type: FragmentType::String,
+                fragment_type,
                 operation,
                 ..
-            } => {
-                if *operation == Operation::Eq {
-                    Ok(())
-                } else {
-                    Err(app_error(
-                        new_context,
-                        "String value type supports only `eq` operation.".into(),
-                    ))
-                }
-            }
+            } if !fragment_type.operations().contains(operation) => Err(app_error(
+                new_context,
+                unsupported_fragment_operation(operation),
+            )),
             _ => Ok(()),
         }
     }
 }
 
+impl KeyFragmentAnyFilter {
+    fn is_valid(&self, context: String) -> Result<(), AppError> {
+        let new_context = format!("{}fragment_any", context);
+        match self {
+            Self {
+                value: FragmentValueType::IntVal(_),
+                fragment_type: FragmentType::String,
+            } => Err(app_error(
+                new_context,
+                "`string` fragment type requires `value` of string type, found integer.".into(),
+            )),
+            Self {
+                value: FragmentValueType::StringVal(_),
+                fragment_type: FragmentType::Integer,
+            } => Err(app_error(
+                new_context,
+                "`integer` fragment type requires `value` of integer type, found string.".into(),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn unsupported_fragment_operation(operation: &Operation) -> String {
+    if *operation == Operation::Fulltext {
+        "`fulltext` operation is supported only by `value` filter of string type.".into()
+    } else {
+        "String value type supports only `eq` operation.".into()
+    }
+}
+
 impl ValueFragmentFilter {
     fn is_valid(&self, context: String) -> Result<(), AppError> {
         let new_context = format!("{}value_fragment", context);
+        if self.position > FRAGMENT_POSITION_MAX {
+            return Err(app_error(new_context, position_out_of_range()));
+        }
 
         match self {
             Self {
@@ -213,24 +637,25 @@ impl ValueFragmentFilter {
                 "`integer` fragment type requires `value` of integer type, found string.".into(),
             )),
             Self {
-                fragment_type: FragmentType::String,
+                fragment_type,
                 operation,
                 ..
-            } => {
-                if *operation == Operation::Eq {
-                    Ok(())
-                } else {
-                    Err(app_error(
-                        new_context,
-                        "String value type supports only `eq` operation.".into(),
-                    ))
-                }
-            }
+            } if !fragment_type.operations().contains(operation) => Err(app_error(
+                new_context,
+                unsupported_fragment_operation(operation),
+            )),
             _ => Ok(()),
         }
     }
 }
 
+fn position_out_of_range() -> String {
+    format!(
+        "`position` out of range, should be less or equal than {}.",
+        FRAGMENT_POSITION_MAX
+    )
+}
+
 fn app_error(parameter: String, reason: String) -> AppError {
     AppError::new_validation_error(
         ValidationErrorCode::InvalidParamenterValue,
@@ -238,6 +663,16 @@ fn app_error(parameter: String, reason: String) -> AppError {
     )
 }
 
+fn valid_limit(limit: u64) -> Result<(), AppError> {
+    if limit > LIMIT_MAX {
+        return Err(app_error(
+            "limit".into(),
+            format!("maximum value {} exceeded", LIMIT_MAX),
+        ));
+    }
+    Ok(())
+}
+
 impl KeyFilter {
     fn is_valid(&self, _: String) -> Result<(), AppError> {
         Ok(())
@@ -249,6 +684,18 @@ impl ValueFilter {
         let new_context = format!("{}value", context);
         self.valid_type(&new_context)?;
         self.valid_operation(&new_context)?;
+        self.valid_coercion(&new_context)?;
+        Ok(())
+    }
+
+    fn valid_coercion(&self, context: &String) -> Result<(), AppError> {
+        if self.coerce_string && !matches!(self.value_type, ValueType::Integer) {
+            let reason = format!(
+                "`coerce_string` is only supported for `integer` value type, found {}",
+                self.value_type.to_type()
+            );
+            return Err(app_error(context.to_owned(), reason));
+        }
         Ok(())
     }
 
@@ -269,11 +716,25 @@ impl ValueFilter {
                 value: ValueData::Binary(_),
                 ..
             } => {}
+            Self {
+                value_type: ValueType::Binary,
+                value: ValueData::String(s),
+                ..
+            } => {
+                decode_binary(s).map_err(|reason| app_error(context.to_owned(), reason))?;
+            }
             Self {
                 value_type: ValueType::Bool,
                 value: ValueData::Bool(_),
                 ..
             } => {}
+            Self {
+                value_type: ValueType::Bignum,
+                value: ValueData::String(s),
+                ..
+            } => {
+                valid_bignum(s).map_err(|reason| app_error(context.to_owned(), reason))?;
+            }
             Self {
                 value_type, value, ..
             } => {
@@ -290,28 +751,94 @@ impl ValueFilter {
     }
 
     fn valid_operation(&self, context: &String) -> Result<(), AppError> {
-        match self {
-            Self {
-                operation: Operation::Eq,
-                ..
-            } => {}
-            Self {
-                value_type: ValueType::Integer,
-                ..
-            } => {}
-            Self {
-                value_type,
-                operation,
-                ..
-            } => {
-                let base_type = value_type.to_type();
-                let op_type = operation.to_type();
-                let reason = format!(
-                    "`{}` value type support only `eq` operation, found {}",
-                    base_type, op_type
-                );
-                return Err(app_error(context.to_owned(), reason));
-            }
+        let operations = self.value_type.operations();
+        if operations.contains(&self.operation) {
+            return Ok(());
+        }
+
+        let reason = format!(
+            "`{}` value type supports only {} operations, found {}",
+            self.value_type.to_type(),
+            operations
+                .iter()
+                .map(|op| format!("`{}`", op.to_type()))
+                .collect::<Vec<_>>()
+                .join(", "),
+            self.operation.to_type()
+        );
+        Err(app_error(context.to_owned(), reason))
+    }
+}
+
+impl KeysFilter {
+    fn is_valid(&self, context: String) -> Result<(), AppError> {
+        if self.0.is_empty() {
+            return Err(app_error(
+                format!("{}keys", context),
+                "at least one key is required.".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl KeyPrefixInFilter {
+    fn is_valid(&self, context: String) -> Result<(), AppError> {
+        let context = format!("{}key_prefix_in", context);
+        if self.0.is_empty() {
+            return Err(app_error(
+                context,
+                "at least one prefix is required.".into(),
+            ));
+        }
+        if self.0.len() > KEY_PREFIXES_MAX {
+            return Err(app_error(
+                context,
+                format!("maximum number of prefixes {} exceeded.", KEY_PREFIXES_MAX),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ValueJsonFilter {
+    fn is_valid(&self, context: String) -> Result<(), AppError> {
+        let context = format!("{}value_json.path", context);
+        if self.path.is_empty() {
+            return Err(app_error(
+                context,
+                "at least one path segment is required.".into(),
+            ));
+        }
+        if self.path.len() > VALUE_JSON_DEPTH_MAX {
+            return Err(app_error(
+                context,
+                format!("maximum depth {} exceeded.", VALUE_JSON_DEPTH_MAX),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl ValueSizeFilter {
+    fn is_valid(&self, context: String) -> Result<(), AppError> {
+        let context = format!("{}value_size", context);
+        // sizes are compared like integer values
+        let operations = ValueType::Integer.operations();
+        if !operations.contains(&self.operation) {
+            let reason = format!(
+                "`value_size` supports only {} operations, found {}",
+                operations
+                    .iter()
+                    .map(|op| format!("`{}`", op.to_type()))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                self.operation.to_type()
+            );
+            return Err(app_error(context, reason));
+        }
+        if self.value < 0 {
+            return Err(app_error(context, "size must be non-negative.".into()));
         }
         Ok(())
     }
@@ -323,6 +850,40 @@ impl AddressFilter {
     }
 }
 
+// binary values are returned as arrays of bytes, and accepted as such too
+pub const BYTE_ARRAY_ENCODING: &str = "byte_array";
+// encodings of binary values accepted in requests, the strings are decoded by `decode_binary`
+pub const BINARY_ENCODINGS: [&str; 3] = [BYTE_ARRAY_ENCODING, "hex", "base64"];
+
+/// Decodes a binary value passed as a string: `0x`-prefixed hex,
+/// or base64 with an optional `base64:` prefix.
+pub fn decode_binary(s: &str) -> Result<Vec<u8>, String> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        if !hex.is_ascii() || hex.len() % 2 != 0 {
+            return Err(format!("invalid hex binary value `{}`.", s));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| format!("invalid hex binary value `{}`.", s))
+    } else {
+        let b64 = s.strip_prefix("base64:").unwrap_or(s);
+        base64::decode(b64).map_err(|_| format!("invalid base64 binary value `{}`.", s))
+    }
+}
+
+fn valid_bignum(s: &str) -> Result<(), String> {
+    if BIGNUM_RE.is_match(s) {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid bignum value `{}`, a decimal number is expected.",
+            s
+        ))
+    }
+}
+
 fn default_limit() -> u64 {
     100u64
 }
@@ -345,9 +906,11 @@ pub enum InFilterValue {
     BoolVal(bool),
     IntVal(i64),
     StringVal(String),
+    // explicit `null`, matches rows where the property is missing
+    Null,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub enum FragmentType {
     #[serde(rename = "string")]
     String,
@@ -355,6 +918,24 @@ pub enum FragmentType {
     Integer,
 }
 
+impl FragmentType {
+    pub const ALL: [FragmentType; 2] = [FragmentType::String, FragmentType::Integer];
+
+    /// Operations supported by the `fragment` and `value_fragment` filters of this type.
+    pub fn operations(&self) -> Vec<Operation> {
+        match self {
+            FragmentType::String => vec![Operation::Eq],
+            FragmentType::Integer => vec![
+                Operation::Eq,
+                Operation::Gt,
+                Operation::Gte,
+                Operation::Lt,
+                Operation::Lte,
+            ],
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq)]
 pub enum Operation {
     #[serde(rename = "eq")]
@@ -367,8 +948,39 @@ pub enum Operation {
     Lt,
     #[serde(rename = "lte")]
     Lte,
+    // word search over value_string, needs a GIN index on
+    // to_tsvector('simple', value_string) to be fast
+    #[serde(rename = "fulltext")]
+    Fulltext,
 }
 
+// names of the RequestFilter and SortItem variants, advertised by /capabilities
+pub const FILTER_KINDS: [&str; 13] = [
+    "and",
+    "or",
+    "in",
+    "fragment",
+    "fragment_any",
+    "value_fragment",
+    "key",
+    "keys",
+    "key_prefix_in",
+    "value_json",
+    "value",
+    "value_size",
+    "address",
+];
+pub const SORT_KINDS: [&str; 8] = [
+    "fragment",
+    "key",
+    "value",
+    "address",
+    "base",
+    "value_fragment",
+    "random",
+    "relevance",
+];
+
 #[derive(Clone, Debug, Deserialize)]
 pub enum RequestFilter {
     #[serde(rename = "and")]
@@ -379,12 +991,22 @@ pub enum RequestFilter {
     In(InFilter),
     #[serde(rename = "fragment")]
     Fragment(KeyFragmentFilter),
+    #[serde(rename = "fragment_any")]
+    FragmentAny(KeyFragmentAnyFilter),
     #[serde(rename = "value_fragment")]
     ValueFragment(ValueFragmentFilter),
     #[serde(rename = "key")]
     Key(KeyFilter),
+    #[serde(rename = "keys")]
+    Keys(KeysFilter),
+    #[serde(rename = "key_prefix_in")]
+    KeyPrefixIn(KeyPrefixInFilter),
+    #[serde(rename = "value_json")]
+    ValueJson(ValueJsonFilter),
     #[serde(rename = "value")]
     Value(ValueFilter),
+    #[serde(rename = "value_size")]
+    ValueSize(ValueSizeFilter),
     #[serde(rename = "address")]
     Address(AddressFilter),
 }
@@ -404,6 +1026,14 @@ pub struct KeyFragmentFilter {
     pub value: FragmentValueType,
 }
 
+/// Matches keys having the fragment value at any position.
+#[derive(Clone, Debug, Deserialize)]
+pub struct KeyFragmentAnyFilter {
+    #[serde(rename = "type")]
+    pub fragment_type: FragmentType,
+    pub value: FragmentValueType,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ValueFragmentFilter {
     #[serde(rename = "type")]
@@ -413,7 +1043,7 @@ pub struct ValueFragmentFilter {
     pub value: FragmentValueType,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 pub enum InItemFilter {
     #[serde(rename = "fragment")]
     Fragment {
@@ -436,9 +1066,8 @@ impl InItemFilter {
     fn is_valid(&self, context: String) -> Result<(), AppError> {
         match self {
             InItemFilter::Fragment { position, .. } => {
-                if *position > 10 {
-                    let reason = "`position` out of range, should be less or equal than 10.".into();
-                    return Err(app_error(context, reason));
+                if *position > FRAGMENT_POSITION_MAX {
+                    return Err(app_error(context, position_out_of_range()));
                 }
             }
             _ => {}
@@ -456,13 +1085,40 @@ impl InItemFilter {
     }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum ValueType {
     String,
     Integer,
     Binary,
     Bool,
+    // decimal numbers beyond i64 stored as strings, the bound is passed as a string too
+    Bignum,
+}
+
+impl ValueType {
+    pub const ALL: [ValueType; 5] = [
+        ValueType::String,
+        ValueType::Integer,
+        ValueType::Binary,
+        ValueType::Bool,
+        ValueType::Bignum,
+    ];
+
+    /// Operations supported by the `value` filter of this type.
+    pub fn operations(&self) -> Vec<Operation> {
+        match self {
+            ValueType::String => vec![Operation::Eq, Operation::Fulltext],
+            ValueType::Integer | ValueType::Bignum => vec![
+                Operation::Eq,
+                Operation::Gt,
+                Operation::Gte,
+                Operation::Lt,
+                Operation::Lte,
+            ],
+            ValueType::Binary | ValueType::Bool => vec![Operation::Eq],
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -474,6 +1130,39 @@ pub struct InFilter {
 #[derive(Clone, Debug, Deserialize)]
 pub struct KeyFilter {
     pub value: String,
+    #[serde(default)]
+    pub operation: KeyOperation,
+}
+
+/// Matches string values holding json, by equality of the element at `path`:
+/// object keys, or array indices written as strings.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ValueJsonFilter {
+    pub path: Vec<String>,
+    pub value: serde_json::Value,
+}
+
+/// Shortcut for an `in` filter by keys only.
+#[derive(Clone, Debug, Deserialize)]
+pub struct KeysFilter(pub Vec<String>);
+
+/// Shortcut for an `or` of `prefix` key filters.
+#[derive(Clone, Debug, Deserialize)]
+pub struct KeyPrefixInFilter(pub Vec<String>);
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyOperation {
+    Eq,
+    Prefix,
+    // case-insensitive substring match, `%` and `_` match literally
+    Ilike,
+}
+
+impl Default for KeyOperation {
+    fn default() -> Self {
+        KeyOperation::Eq
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -482,6 +1171,17 @@ pub struct ValueFilter {
     pub value_type: ValueType,
     pub operation: Operation,
     pub value: ValueData,
+    // also match numbers stored as strings, for datasets with mixed value types
+    #[serde(default)]
+    pub coerce_string: bool,
+}
+
+/// Compares the size of string values in chars, or of binary values in bytes;
+/// integer and bool values never match.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ValueSizeFilter {
+    pub operation: Operation,
+    pub value: i64,
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -536,6 +1236,15 @@ pub enum SortItemDirection {
     Desc,
 }
 
+// where null values go, postgres puts them last in ascending order by default;
+// only the sort items of nullable columns take it
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortNulls {
+    First,
+    Last,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub enum SortItem {
     #[serde(rename = "fragment")]
@@ -544,11 +1253,18 @@ pub enum SortItem {
         #[serde(rename = "type")]
         fragment_type: FragmentType,
         direction: SortItemDirection,
+        nulls: Option<SortNulls>,
     },
     #[serde(rename = "key")]
     Key { direction: SortItemDirection },
+    // entries of other value types are null in the column of `type`
     #[serde(rename = "value")]
-    Value { direction: SortItemDirection },
+    Value {
+        #[serde(rename = "type")]
+        value_type: Option<ValueType>,
+        direction: SortItemDirection,
+        nulls: Option<SortNulls>,
+    },
     #[serde(rename = "address")]
     Address { direction: SortItemDirection },
     //default order by data_entries.uid
@@ -560,12 +1276,75 @@ pub enum SortItem {
         #[serde(rename = "type")]
         fragment_type: FragmentType,
         direction: SortItemDirection,
+        nulls: Option<SortNulls>,
+    },
+    // random sample for QA, deterministic when seeded;
+    // pages are not stable across requests without a seed
+    #[serde(rename = "random")]
+    Random { seed: Option<i64> },
+    // exact key match first, then shorter keys, for key prefix searches;
+    // the prefix is taken from the key filter
+    #[serde(rename = "relevance")]
+    Relevance {
+        #[serde(skip)]
+        key: String,
     },
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct RequestSort(pub Vec<SortItem>);
 
+impl RequestSort {
+    fn is_valid(&self) -> Result<(), AppError> {
+        self.0
+            .iter()
+            .enumerate()
+            .try_for_each(|(idx, item)| match item {
+                SortItem::Fragment { position, .. } | SortItem::ValueFragment { position, .. }
+                    if *position > FRAGMENT_POSITION_MAX =>
+                {
+                    Err(app_error(format!("sort[{}]", idx), position_out_of_range()))
+                }
+                // values are stored in a column per type, there is no single one to sort by
+                SortItem::Value {
+                    value_type: None, ..
+                } => Err(app_error(
+                    format!("sort[{}].type", idx),
+                    format!(
+                        "`value` sort requires a `type`, one of {}.",
+                        ValueType::ALL
+                            .iter()
+                            .map(|t| format!("`{}`", t.to_type()))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                )),
+                _ => Ok(()),
+            })
+    }
+
+    fn has_relevance(&self) -> bool {
+        self.0
+            .iter()
+            .any(|item| matches!(item, SortItem::Relevance { .. }))
+    }
+
+    /// Sets the searched key prefix on the relevance items.
+    pub fn with_relevance_key(self, key: &str) -> Self {
+        RequestSort(
+            self.0
+                .into_iter()
+                .map(|item| match item {
+                    SortItem::Relevance { .. } => SortItem::Relevance {
+                        key: key.to_string(),
+                    },
+                    item => item,
+                })
+                .collect(),
+        )
+    }
+}
+
 pub trait ToType {
     fn to_type(&self) -> String;
 }
@@ -597,6 +1376,7 @@ impl ToType for ValueType {
             ValueType::Integer => "integer".to_string(),
             ValueType::Binary => "binary".to_string(),
             ValueType::Bool => "bool".to_string(),
+            ValueType::Bignum => "bignum".to_string(),
         }
     }
 }
@@ -608,6 +1388,7 @@ impl ToType for InFilterValue {
             InFilterValue::BoolVal(_) => "bool".to_string(),
             InFilterValue::IntVal(_) => "integer".to_string(),
             InFilterValue::StringVal(_) => "string".to_string(),
+            InFilterValue::Null => "null".to_string(),
         }
     }
 }
@@ -631,6 +1412,61 @@ impl ToType for Operation {
             Operation::Gte => "gte".to_string(),
             Operation::Lt => "lt".to_string(),
             Operation::Lte => "lte".to_string(),
+            Operation::Fulltext => "fulltext".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search_request(json: &str) -> SearchRequest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn zero_limit_is_valid() {
+        let req = search_request(
+            r#"{"filter": {"address": {"value": "3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP"}}, "limit": 0}"#,
+        );
+
+        assert_eq!(req.limit, 0);
+        assert!(req.is_valid().is_ok());
+    }
+
+    #[test]
+    fn limit_over_max_is_rejected() {
+        let req = search_request(&format!(r#"{{"limit": {}}}"#, LIMIT_MAX + 1));
+
+        assert!(req.is_valid().is_err());
+    }
+
+    #[test]
+    fn value_sort_without_type_is_rejected() {
+        let req = search_request(r#"{"sort": [{"value": {"direction": "asc"}}]}"#);
+
+        match req.is_valid() {
+            Err(AppError::ValidationError(_, code, Some(details))) => {
+                assert_eq!(code, ValidationErrorCode::InvalidParamenterValue as u32);
+                assert_eq!(details.parameter, "sort[0].type");
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn repeated_in_properties_are_rejected() {
+        let req = search_request(
+            r#"{"filter": {"in": {"properties": [{"key": {}}, {"address": {}}, {"key": {}}], "values": [["a", "b", "a"]]}}}"#,
+        );
+
+        match req.is_valid() {
+            Err(AppError::ValidationError(_, code, Some(details))) => {
+                assert_eq!(code, ValidationErrorCode::InvalidParamenterValue as u32);
+                assert_eq!(details.parameter, "filter.in.properties[2]");
+            }
+            res => panic!("unexpected result {:?}", res),
         }
     }
 }
@@ -0,0 +1,284 @@
+//! Protobuf encoding of responses, the messages mirror `proto/data_entries.proto`.
+
+use warp::http::header::{HeaderValue, CONTENT_TYPE};
+use warp::reply::Response;
+
+use super::{
+    DataEntriesResponse, DataEntryFragment, DataEntryType, DataEntryValueFragment, MgetResponse,
+};
+
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+pub fn is_protobuf_accepted(accept: &Option<String>) -> bool {
+    accept
+        .as_ref()
+        .map(|accept| accept.contains(PROTOBUF_CONTENT_TYPE))
+        .unwrap_or(false)
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DataEntriesMessage {
+    #[prost(message, repeated, tag = "1")]
+    pub entries: Vec<DataEntryMessage>,
+    #[prost(bool, tag = "2")]
+    pub has_next_page: bool,
+    #[prost(uint64, tag = "3")]
+    pub limit: u64,
+    #[prost(uint64, tag = "4")]
+    pub offset: u64,
+    #[prost(bool, tag = "5")]
+    pub partial: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MgetMessage {
+    #[prost(message, repeated, tag = "1")]
+    pub entries: Vec<MgetEntryMessage>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct MgetEntryMessage {
+    #[prost(message, optional, tag = "1")]
+    pub entry: Option<DataEntryMessage>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DataEntryMessage {
+    #[prost(string, optional, tag = "1")]
+    pub address: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    pub key: Option<String>,
+    #[prost(int32, optional, tag = "3")]
+    pub height: Option<i32>,
+    #[prost(oneof = "Value", tags = "4, 5, 6, 7")]
+    pub value: Option<Value>,
+    #[prost(message, repeated, tag = "8")]
+    pub key_fragments: Vec<FragmentMessage>,
+    #[prost(message, repeated, tag = "9")]
+    pub value_fragments: Vec<FragmentMessage>,
+    #[prost(bool, tag = "10")]
+    pub truncated: bool,
+    #[prost(string, optional, tag = "11")]
+    pub id: Option<String>,
+    #[prost(int32, optional, tag = "12")]
+    pub value_size: Option<i32>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum Value {
+    #[prost(bytes, tag = "4")]
+    Binary(Vec<u8>),
+    #[prost(bool, tag = "5")]
+    Bool(bool),
+    #[prost(int64, tag = "6")]
+    Integer(i64),
+    #[prost(string, tag = "7")]
+    String(String),
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct FragmentMessage {
+    #[prost(oneof = "FragmentValue", tags = "1, 2")]
+    pub value: Option<FragmentValue>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum FragmentValue {
+    #[prost(int64, tag = "1")]
+    Integer(i64),
+    #[prost(string, tag = "2")]
+    String(String),
+}
+
+impl From<super::DataEntry> for DataEntryMessage {
+    fn from(v: super::DataEntry) -> Self {
+        let (key_fragments, value_fragments) = match v.fragments {
+            Some(fragments) => (
+                fragments.key.into_iter().map(Into::into).collect(),
+                fragments.value.into_iter().map(Into::into).collect(),
+            ),
+            None => (vec![], vec![]),
+        };
+
+        Self {
+            address: v.address,
+            key: v.key,
+            height: v.height.flatten(),
+            value: v.value.map(|value| match value {
+                DataEntryType::BinaryVal(v) => Value::Binary(v),
+                DataEntryType::BoolVal(v) => Value::Bool(v),
+                DataEntryType::IntVal(v) => Value::Integer(v),
+                DataEntryType::StringVal(v) => Value::String(v),
+            }),
+            key_fragments,
+            value_fragments,
+            truncated: v.truncated,
+            id: v.id,
+            value_size: v.value_size,
+        }
+    }
+}
+
+impl From<DataEntryFragment> for FragmentMessage {
+    fn from(v: DataEntryFragment) -> Self {
+        let value = match v {
+            DataEntryFragment::Integer { value } => FragmentValue::Integer(value),
+            DataEntryFragment::String { value } => FragmentValue::String(value),
+        };
+        Self { value: Some(value) }
+    }
+}
+
+impl From<DataEntryValueFragment> for FragmentMessage {
+    fn from(v: DataEntryValueFragment) -> Self {
+        let value = match v {
+            DataEntryValueFragment::Integer { value } => FragmentValue::Integer(value),
+            DataEntryValueFragment::String { value } => FragmentValue::String(value),
+        };
+        Self { value: Some(value) }
+    }
+}
+
+impl DataEntriesResponse {
+    pub fn into_protobuf_response(self) -> Response {
+        let message = DataEntriesMessage {
+            entries: self.entries.into_iter().map(Into::into).collect(),
+            has_next_page: self.has_next_page,
+            partial: self.partial,
+            limit: self.limit,
+            offset: self.offset,
+        };
+        protobuf_response(&message)
+    }
+}
+
+impl MgetResponse {
+    pub fn into_protobuf_response(self) -> Response {
+        let message = MgetMessage {
+            entries: self
+                .entries
+                .into_iter()
+                .map(|entry| MgetEntryMessage {
+                    entry: entry.map(Into::into),
+                })
+                .collect(),
+        };
+        protobuf_response(&message)
+    }
+}
+
+fn protobuf_response(message: &impl prost::Message) -> Response {
+    let mut res = Response::new(message.encode_to_vec().into());
+    res.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(PROTOBUF_CONTENT_TYPE),
+    );
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::DataEntry;
+    use crate::data_entries;
+    use prost::Message;
+    use warp::hyper::body::to_bytes;
+
+    fn entry() -> DataEntry {
+        DataEntry::from(data_entries::DataEntry {
+            uid: 7,
+            address: "3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP".into(),
+            key: "price_7".into(),
+            height: Some(2_500_000),
+            value_string: Some("12@waves".into()),
+            value_size: Some(8),
+            fragment_0_string: Some("price".into()),
+            fragment_1_integer: Some(7),
+            value_fragment_0_integer: Some(12),
+            value_fragment_1_string: Some("waves".into()),
+            ..Default::default()
+        })
+        .with_id(true)
+        .with_max_value_len(Some(2))
+    }
+
+    #[tokio::test]
+    async fn entries_decode_as_sent() {
+        let id = entry().id;
+        let res = DataEntriesResponse {
+            entries: vec![entry()],
+            has_next_page: true,
+            limit: 1,
+            offset: 2,
+            partial: true,
+        }
+        .into_protobuf_response();
+        assert_eq!(res.headers()[CONTENT_TYPE], PROTOBUF_CONTENT_TYPE);
+
+        let body = to_bytes(res.into_body()).await.unwrap();
+        let message = DataEntriesMessage::decode(body).unwrap();
+
+        assert!(message.has_next_page);
+        assert_eq!(message.limit, 1);
+        assert_eq!(message.offset, 2);
+        assert!(message.partial);
+        assert_eq!(message.entries.len(), 1);
+
+        let entry = &message.entries[0];
+        assert!(id.is_some());
+        assert_eq!(entry.id, id);
+        assert_eq!(
+            entry.address.as_deref(),
+            Some("3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP")
+        );
+        assert_eq!(entry.key.as_deref(), Some("price_7"));
+        assert_eq!(entry.height, Some(2_500_000));
+        assert_eq!(entry.value, Some(Value::String("12".into())));
+        assert!(entry.truncated);
+        assert_eq!(entry.value_size, Some(8));
+        assert_eq!(
+            entry.key_fragments,
+            vec![
+                FragmentMessage {
+                    value: Some(FragmentValue::String("price".into()))
+                },
+                FragmentMessage {
+                    value: Some(FragmentValue::Integer(7))
+                },
+            ]
+        );
+        assert_eq!(
+            entry.value_fragments,
+            vec![
+                FragmentMessage {
+                    value: Some(FragmentValue::Integer(12))
+                },
+                FragmentMessage {
+                    value: Some(FragmentValue::String("waves".into()))
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_mget_entries_decode_as_empty() {
+        let res = MgetResponse {
+            entries: vec![None, Some(entry())],
+            by_address: None,
+        }
+        .into_protobuf_response();
+
+        let body = to_bytes(res.into_body()).await.unwrap();
+        let message = MgetMessage::decode(body).unwrap();
+
+        assert_eq!(message.entries.len(), 2);
+        assert_eq!(message.entries[0].entry, None);
+        assert_eq!(
+            message.entries[1]
+                .entry
+                .as_ref()
+                .and_then(|e| e.key.as_deref()),
+            Some("price_7")
+        );
+    }
+}
@@ -0,0 +1,79 @@
+//! Token bucket rate limiting of api requests, per client.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// idle clients are forgotten once this many are tracked
+const MAX_CLIENTS: usize = 10_000;
+
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+    // tokens per second
+    rate: f64,
+    burst: f64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    /// A zero burst is treated like a missing one and defaults to the rate,
+    /// a bucket of no tokens would reject every request.
+    pub fn new(rate: u32, burst: u32) -> Self {
+        let burst = if burst == 0 { rate } else { burst };
+        Self {
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+            rate: rate as f64,
+            burst: burst.max(1) as f64,
+        }
+    }
+
+    /// Takes a token of the client, or returns the time until the next one is available.
+    pub fn check(&self, client: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_CLIENTS {
+            // a refilled bucket is the same as a missing one
+            buckets.retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+        }
+
+        let bucket = buckets.entry(client.to_owned()).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_burst_defaults_to_rate() {
+        let rate_limiter = RateLimiter::new(3, 0);
+        for _ in 0..3 {
+            assert!(rate_limiter.check("ip:127.0.0.1").is_ok());
+        }
+        assert!(rate_limiter.check("ip:127.0.0.1").is_err());
+    }
+}
@@ -1,14 +1,170 @@
 use super::parsing::{
-    AddressFilter, AndFilter, FragmentType, FragmentValueType, InFilter, InFilterValue,
-    InItemFilter, KeyFilter, KeyFragmentFilter, MgetEntries, Operation, OrFilter, RequestFilter,
-    RequestSort, SortItem, SortItemDirection, ValueData, ValueFilter, ValueFragmentFilter,
-    ValueType,
+    decode_binary, AddressFilter, AndFilter, FragmentType, FragmentValueType, InFilter,
+    InFilterValue, InItemFilter, KeyFilter, KeyFragmentAnyFilter, KeyFragmentFilter, KeyOperation,
+    KeyPrefixInFilter, KeysFilter, MgetEntries, Operation, OrFilter, RequestFilter, RequestSort,
+    SortItem, SortItemDirection, SortNulls, ToType, ValueData, ValueFilter, ValueFragmentFilter,
+    ValueJsonFilter, ValueSizeFilter, ValueType, BIGNUM_PATTERN, FRAGMENT_POSITION_MAX,
 };
 use crate::data_entries::{SqlSort, SqlWhere};
 use crate::text_utils::pg_escape;
 use base64::encode;
 use md5::compute as md5;
 
+/// Settings of the deployment the sql of filters depends on.
+#[derive(Clone, Debug)]
+pub struct SqlContext {
+    // string values shorter than this are matched by plain equality,
+    // the md5 clause only helps the functional index for long values
+    pub md5_min_value_len: usize,
+}
+
+impl SqlContext {
+    /// Empty string when the filter matches everything, so that no condition is added.
+    pub fn filter(&self, v: RequestFilter) -> SqlWhere {
+        simplify(v).map_or_else(String::new, |f| self.filter_sql(f))
+    }
+
+    // expects a simplified filter
+    fn filter_sql(&self, v: RequestFilter) -> SqlWhere {
+        match v {
+            RequestFilter::And(n) => self.and_sql(n),
+            RequestFilter::Or(n) => self.or_sql(n),
+            RequestFilter::In(n) => n.into(),
+            RequestFilter::Fragment(n) => n.into(),
+            RequestFilter::FragmentAny(n) => n.into(),
+            RequestFilter::ValueFragment(n) => n.into(),
+            RequestFilter::Key(n) => n.into(),
+            RequestFilter::Keys(n) => n.into(),
+            RequestFilter::KeyPrefixIn(n) => n.into(),
+            RequestFilter::ValueJson(n) => n.into(),
+            RequestFilter::Value(n) => self.value(n),
+            RequestFilter::ValueSize(n) => n.into(),
+            RequestFilter::Address(n) => n.into(),
+        }
+    }
+
+    fn and_sql(&self, v: AndFilter) -> SqlWhere {
+        if v.0.len() > 0 {
+            format!(
+                "({})",
+                v.0.into_iter()
+                    .map(|f| self.filter_sql(f))
+                    .collect::<Vec<String>>()
+                    .join(" AND ")
+            )
+        } else {
+            "1=1".to_string()
+        }
+    }
+
+    fn or_sql(&self, v: OrFilter) -> SqlWhere {
+        if v.0.len() > 0 {
+            format!(
+                "({})",
+                v.0.into_iter()
+                    .map(|f| self.filter_sql(f))
+                    .collect::<Vec<String>>()
+                    .join(" OR ")
+            )
+        } else {
+            "1=1".to_string()
+        }
+    }
+
+    pub fn value(&self, v: ValueFilter) -> SqlWhere {
+        match v {
+            ValueFilter {
+                value: ValueData::Binary(v),
+                ..
+            } => binary_value_sql(v),
+            ValueFilter {
+                value_type: ValueType::Binary,
+                value: ValueData::String(v),
+                ..
+            } => {
+                // decoding is checked in ValueFilter::is_valid
+                binary_value_sql(decode_binary(&v).unwrap_or_default())
+            }
+            ValueFilter {
+                value_type: ValueType::Bignum,
+                operation,
+                value: ValueData::String(v),
+                ..
+            } => format!(
+                "{} {} '{}'::numeric",
+                bignum_sql("value_string"),
+                SqlWhere::from(operation),
+                pg_escape(&v)
+            ),
+            ValueFilter {
+                operation: Operation::Fulltext,
+                value: ValueData::String(v),
+                ..
+            } => format!(
+                "to_tsvector('simple', value_string) @@ plainto_tsquery('simple', '{}')",
+                pg_escape(&v.as_str())
+            ),
+            ValueFilter {
+                value: ValueData::String(v),
+                ..
+            } if v.len() < self.md5_min_value_len => {
+                format!("value_string = '{}'", pg_escape(&v.as_str()))
+            }
+            ValueFilter {
+                value: ValueData::String(v),
+                ..
+            } => format!(
+                "value_string = '{}' AND md5(value_string) = '{:x}'",
+                pg_escape(&v.as_str()),
+                md5(&v.as_str())
+            ),
+            ValueFilter {
+                value: ValueData::Bool(v),
+                ..
+            } => format!("value_bool = {} AND value_bool IS NOT NULL", v),
+            ValueFilter {
+                operation,
+                value: ValueData::Integer(v),
+                coerce_string: true,
+                ..
+            } => {
+                let operation = SqlWhere::from(operation);
+                // the cast is evaluated only for values that look like integers,
+                // otherwise it would fail the whole query
+                format!(
+                    "(value_integer {} {} OR (CASE WHEN value_string ~ '^-?[0-9]+$' THEN value_string::numeric END) {} {})",
+                    operation, v, operation, v
+                )
+            }
+            ValueFilter {
+                operation,
+                value: ValueData::Integer(v),
+                ..
+            } => format!("value_integer {} {}", SqlWhere::from(operation), v),
+        }
+    }
+
+    pub fn mget(&self, v: MgetEntries) -> SqlWhere {
+        let pairs = format!(
+            "(address, key) IN ({})",
+            v.address_key_pairs
+                .into_iter()
+                .map(|entry| format!(
+                    "('{}', '{}')",
+                    pg_escape(entry.address.as_str()),
+                    pg_escape(entry.key.as_str())
+                ))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+
+        match v.filter.map(|f| self.filter(f)) {
+            Some(filter) if filter.len() > 0 => format!("{} AND {}", pairs, filter),
+            _ => pairs,
+        }
+    }
+}
+
 impl From<InFilterValue> for SqlWhere {
     fn from(v: InFilterValue) -> Self {
         match v {
@@ -16,6 +172,7 @@ impl From<InFilterValue> for SqlWhere {
             InFilterValue::BoolVal(b) => format!("{}", b.to_owned()),
             InFilterValue::IntVal(n) => format!("{}", n),
             InFilterValue::StringVal(s) => format!("'{}'", s.to_owned()),
+            InFilterValue::Null => "NULL".into(),
         }
     }
 }
@@ -24,7 +181,7 @@ impl From<FragmentValueType> for SqlWhere {
     fn from(v: FragmentValueType) -> Self {
         match v {
             FragmentValueType::IntVal(n) => format!("{}", n),
-            FragmentValueType::StringVal(s) => format!("'{}'", s.to_owned()),
+            FragmentValueType::StringVal(s) => format!("'{}'", pg_escape(&s)),
         }
     }
 }
@@ -45,54 +202,39 @@ impl From<Operation> for SqlWhere {
             Operation::Gte => ">=".into(),
             Operation::Lt => "<".into(),
             Operation::Lte => "<=".into(),
+            Operation::Fulltext => "@@".into(),
         }
     }
 }
 
-impl From<RequestFilter> for SqlWhere {
-    fn from(v: RequestFilter) -> Self {
-        match v {
-            RequestFilter::And(n) => n.into(),
-            RequestFilter::Or(n) => n.into(),
-            RequestFilter::In(n) => n.into(),
-            RequestFilter::Fragment(n) => n.into(),
-            RequestFilter::ValueFragment(n) => n.into(),
-            RequestFilter::Key(n) => n.into(),
-            RequestFilter::Value(n) => n.into(),
-            RequestFilter::Address(n) => n.into(),
-        }
-    }
-}
-
-impl From<AndFilter> for SqlWhere {
-    fn from(v: AndFilter) -> Self {
-        if v.0.len() > 0 {
-            format!(
-                "({})",
-                v.0.iter()
-                    .map(|n| n.to_owned().into())
-                    .collect::<Vec<String>>()
-                    .join(" AND ")
-            )
-        } else {
-            "1=1".to_string()
+/// Drops empty `and`/`or` combinators and unwraps the single-child ones,
+/// `None` means the filter reduces to a tautology.
+fn simplify(v: RequestFilter) -> Option<RequestFilter> {
+    match v {
+        RequestFilter::And(AndFilter(filters)) => {
+            // tautologies don't restrict a conjunction, they are dropped
+            let mut filters: Vec<RequestFilter> =
+                filters.into_iter().filter_map(simplify).collect();
+            match filters.len() {
+                0 => None,
+                1 => filters.pop(),
+                _ => Some(RequestFilter::And(AndFilter(filters))),
+            }
         }
-    }
-}
-
-impl From<OrFilter> for SqlWhere {
-    fn from(v: OrFilter) -> Self {
-        if v.0.len() > 0 {
-            format!(
-                "({})",
-                v.0.iter()
-                    .map(|n| n.to_owned().into())
-                    .collect::<Vec<String>>()
-                    .join(" OR ")
-            )
-        } else {
-            "1=1".to_string()
+        RequestFilter::Or(OrFilter(filters)) => {
+            // a single tautology makes the whole disjunction one
+            let mut filters = filters
+                .into_iter()
+                .map(simplify)
+                .collect::<Option<Vec<RequestFilter>>>()?;
+            match filters.len() {
+                0 => None,
+                1 => filters.pop(),
+                _ => Some(RequestFilter::Or(OrFilter(filters))),
+            }
         }
+        RequestFilter::In(n) if n.properties.is_empty() || n.values.is_empty() => None,
+        n => Some(n),
     }
 }
 
@@ -114,7 +256,7 @@ impl From<InItemFilter> for SqlWhere {
                 value_type: ValueType::Integer,
             } => "value_integer".into(),
             InItemFilter::Value {
-                value_type: ValueType::String,
+                value_type: ValueType::String | ValueType::Bignum,
             } => "value_string".into(),
             InItemFilter::Address {} => "address".into(),
         }
@@ -123,41 +265,96 @@ impl From<InItemFilter> for SqlWhere {
 
 impl From<InFilter> for SqlWhere {
     fn from(v: InFilter) -> Self {
-        let values: Vec<String> = v
+        let columns: Vec<SqlWhere> = v
+            .properties
+            .iter()
+            .map(|p| {
+                let v = SqlWhere::from(p.to_owned());
+                pg_escape(&v.as_str()).into()
+            })
+            .collect();
+
+        // rows with nulls can't be matched by IN, every such row gets its own condition
+        let (null_rows, rows): (Vec<_>, Vec<_>) = v
             .values
-            .clone()
             .into_iter()
-            .map(|rows| {
-                rows.into_iter()
-                    .map(|vt| {
-                        let v: String = vt.into();
-                        pg_escape(v.trim_matches('\'')).into()
-                    })
+            .partition(|row| row.iter().any(|v| matches!(v, InFilterValue::Null)));
+
+        let mut conditions: Vec<SqlWhere> = vec![];
+
+        let values: Vec<String> = rows
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .zip(v.properties.iter())
+                    .map(|(vt, property)| in_value_sql(vt, property))
                     .collect::<Vec<String>>()
                     .join("','")
             })
             .map(|row| format!("('{}')", row))
             .collect();
 
-        if v.properties.len() > 0 && values.len() > 0 {
-            format!(
+        if values.len() > 0 {
+            conditions.push(format!(
                 "(({}) IN ({}))",
-                v.properties
-                    .iter()
-                    .map(|p| {
-                        let v = SqlWhere::from(p.to_owned());
-                        pg_escape(&v.as_str()).into()
-                    })
-                    .collect::<Vec<SqlWhere>>()
-                    .join(","),
+                columns.join(","),
                 values.join(",")
-            )
-        } else {
+            ));
+        }
+
+        conditions.extend(null_rows.into_iter().map(|row| {
+            let row_conditions = row
+                .into_iter()
+                .zip(v.properties.iter())
+                .zip(columns.iter())
+                .map(|((vt, property), column)| match vt {
+                    InFilterValue::Null => format!("{} IS NULL", column),
+                    vt => format!("{} = '{}'", column, in_value_sql(vt, property)),
+                })
+                .collect::<Vec<String>>();
+            format!("({})", row_conditions.join(" AND "))
+        }));
+
+        if columns.len() == 0 || conditions.len() == 0 {
             "1=1".to_string()
+        } else if conditions.len() == 1 {
+            conditions.remove(0)
+        } else {
+            format!("({})", conditions.join(" OR "))
+        }
+    }
+}
+
+// escaped value without the surrounding quotes
+fn in_value_sql(value: InFilterValue, property: &InItemFilter) -> String {
+    let v: String = in_value(value, property).into();
+    pg_escape(v.trim_matches('\'')).into()
+}
+
+// binary values may come as strings, they are resolved by the property type
+fn in_value(value: InFilterValue, property: &InItemFilter) -> InFilterValue {
+    match (property, value) {
+        (
+            InItemFilter::Value {
+                value_type: ValueType::Binary,
+            },
+            InFilterValue::StringVal(s),
+        ) => {
+            // decoding is checked in InFilter::is_valid
+            InFilterValue::BinaryVal(decode_binary(&s).unwrap_or_default())
         }
+        (_, value) => value,
     }
 }
 
+fn binary_value_sql(v: Vec<u8>) -> SqlWhere {
+    let v = encode(v);
+    format!(
+        "value_binary = '{}' AND md5(value_binary) = md5('{}')",
+        v, v
+    )
+}
+
 impl From<KeyFragmentFilter> for SqlWhere {
     fn from(v: KeyFragmentFilter) -> Self {
         format!(
@@ -170,6 +367,21 @@ impl From<KeyFragmentFilter> for SqlWhere {
     }
 }
 
+impl From<KeyFragmentAnyFilter> for SqlWhere {
+    fn from(v: KeyFragmentAnyFilter) -> Self {
+        let fragment_type = SqlWhere::from(v.fragment_type);
+        let value = match v.value {
+            FragmentValueType::IntVal(n) => n.to_string(),
+            FragmentValueType::StringVal(s) => format!("'{}'", pg_escape(&s)),
+        };
+        let positions = (0..=FRAGMENT_POSITION_MAX)
+            .map(|position| format!("fragment_{}_{} = {}", position, fragment_type, value))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        format!("({})", positions)
+    }
+}
+
 impl From<ValueFragmentFilter> for SqlWhere {
     fn from(v: ValueFragmentFilter) -> Self {
         format!(
@@ -184,60 +396,127 @@ impl From<ValueFragmentFilter> for SqlWhere {
 
 impl From<KeyFilter> for SqlWhere {
     fn from(v: KeyFilter) -> Self {
-        format!("key = '{}'", pg_escape(v.value.as_str()))
+        match v.operation {
+            KeyOperation::Eq => format!("key = '{}'", pg_escape(v.value.as_str())),
+            KeyOperation::Prefix => format!(
+                "key LIKE '{}%' ESCAPE '!'",
+                pg_escape(&like_escape(v.value.as_str()))
+            ),
+            KeyOperation::Ilike => format!(
+                "key ILIKE '%{}%' ESCAPE '!'",
+                pg_escape(&like_escape(v.value.as_str()))
+            ),
+        }
     }
 }
 
-impl From<ValueFilter> for SqlWhere {
-    fn from(v: ValueFilter) -> Self {
-        match v {
-            ValueFilter {
-                value: ValueData::Binary(v),
-                ..
-            } => {
-                let v = encode(v);
-                format!(
-                    "value_binary = '{}' AND md5(value_binary) = md5('{}')",
-                    v, v
-                )
-            }
-            ValueFilter {
-                value: ValueData::String(v),
-                ..
-            } => format!(
-                "value_string = '{}' AND md5(value_string) = '{:x}'",
-                pg_escape(&v.as_str()),
-                md5(&v.as_str())
-            ),
-            ValueFilter {
-                value: ValueData::Bool(v),
-                ..
-            } => format!("value_bool = {} AND value_bool IS NOT NULL", v),
-            ValueFilter {
-                operation,
-                value: ValueData::Integer(v),
-                ..
-            } => format!("value_integer {} {}", SqlWhere::from(operation), v),
-        }
+impl From<KeysFilter> for SqlWhere {
+    fn from(v: KeysFilter) -> Self {
+        format!(
+            "key IN ({})",
+            v.0.iter()
+                .map(|key| format!("'{}'", pg_escape(key.as_str())))
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
+
+impl From<KeyPrefixInFilter> for SqlWhere {
+    fn from(v: KeyPrefixInFilter) -> Self {
+        format!(
+            "({})",
+            v.0.iter()
+                .map(|prefix| format!(
+                    "key LIKE '{}%' ESCAPE '!'",
+                    pg_escape(&like_escape(prefix.as_str()))
+                ))
+                .collect::<Vec<_>>()
+                .join(" OR ")
+        )
+    }
+}
+
+impl From<ValueJsonFilter> for SqlWhere {
+    fn from(v: ValueJsonFilter) -> Self {
+        let path = v
+            .path
+            .iter()
+            .map(|segment| format!("'{}'", pg_escape(segment.as_str())))
+            .collect::<Vec<_>>()
+            .join(",");
+        // CASE keeps the cast from running on values which don't look like json,
+        // a value starting like json but malformed still fails the query with a validation error
+        format!(
+            r"(CASE WHEN value_string ~ '^\s*[\[{{]' THEN value_string::jsonb #> ARRAY[{}] END) = '{}'::jsonb",
+            path,
+            pg_escape(&v.value.to_string())
+        )
+    }
+}
+
+impl From<ValueSizeFilter> for SqlWhere {
+    fn from(v: ValueSizeFilter) -> Self {
+        format!(
+            "coalesce(length(value_string), octet_length(value_binary)) {} {}",
+            SqlWhere::from(v.operation),
+            v.value
+        )
     }
 }
 
+// `!` is used as the LIKE escape char, unlike a backslash it can't be mistaken for a string escape
+fn like_escape(s: &str) -> String {
+    s.replace('!', "!!").replace('%', "!%").replace('_', "!_")
+}
+
+// the cast is evaluated only for values that look like numbers,
+// otherwise it would fail the whole query
+fn bignum_sql(column: &str) -> String {
+    format!(
+        "(CASE WHEN {column} ~ '{}' THEN {column}::numeric END)",
+        pg_escape(BIGNUM_PATTERN),
+        column = column
+    )
+}
+
 impl From<AddressFilter> for SqlWhere {
     fn from(v: AddressFilter) -> Self {
         format!("address = '{}'", pg_escape(&v.value.as_str()))
     }
 }
 
+// uid breaks the ties of the requested items, so that pages are stable
 impl From<RequestSort> for SqlSort {
     fn from(v: RequestSort) -> SqlSort {
-        v.0.clone()
-            .into_iter()
-            .map(|sort_item| sort_item.into())
-            .collect::<Vec<String>>()
-            .join(",")
+        let has_uid =
+            v.0.iter()
+                .any(|sort_item| matches!(sort_item, SortItem::Base { .. }));
+        let mut items =
+            v.0.into_iter()
+                .map(|sort_item| sort_item.into())
+                .collect::<Vec<String>>();
+        if !has_uid {
+            items.push("de.uid ASC".into());
+        }
+        items.join(",")
+    }
+}
+
+fn order_by(column: &str, direction: SortItemDirection, nulls: Option<SortNulls>) -> SqlSort {
+    match nulls {
+        Some(nulls) => format!(
+            "{} {} {}",
+            column,
+            SqlSort::from(direction),
+            SqlSort::from(nulls)
+        ),
+        None => format!("{} {}", column, SqlSort::from(direction)),
     }
 }
 
+// columns are qualified, so that the outer search query sorts by the
+// real values even when they are projected out of the response
 impl From<SortItem> for SqlSort {
     fn from(v: SortItem) -> Self {
         match v {
@@ -245,25 +524,54 @@ impl From<SortItem> for SqlSort {
                 position,
                 fragment_type,
                 direction,
-            } => format!(
-                "fragment_{}_{} {}",
-                position,
-                SqlSort::from(fragment_type),
-                SqlSort::from(direction)
+                nulls,
+            } => order_by(
+                &format!("de.fragment_{}_{}", position, SqlSort::from(fragment_type)),
+                direction,
+                nulls,
             ),
-            SortItem::Key { direction } => format!("key {}", SqlSort::from(direction)),
-            SortItem::Value { direction } => format!("value {}", SqlSort::from(direction)),
-            SortItem::Address { direction } => format!("address {}", SqlSort::from(direction)),
-            SortItem::Base { direction } => format!("uid {}", SqlSort::from(direction)),
+            SortItem::Key { direction } => format!("de.key {}", SqlSort::from(direction)),
+            SortItem::Value {
+                value_type: Some(ValueType::Bignum),
+                direction,
+                nulls,
+            } => order_by(&bignum_sql("de.value_string"), direction, nulls),
+            SortItem::Value {
+                value_type: Some(value_type),
+                direction,
+                nulls,
+            } => order_by(
+                &format!("de.value_{}", value_type.to_type()),
+                direction,
+                nulls,
+            ),
+            // rejected by RequestSort::is_valid, there is no `value` column to sort by
+            SortItem::Value {
+                value_type: None,
+                direction,
+                ..
+            } => format!("de.uid {}", SqlSort::from(direction)),
+            SortItem::Address { direction } => format!("de.address {}", SqlSort::from(direction)),
+            SortItem::Base { direction } => format!("de.uid {}", SqlSort::from(direction)),
             SortItem::ValueFragment {
                 position,
                 fragment_type,
                 direction,
-            } => format!(
-                "value_fragment_{}_{} {}",
-                position,
-                SqlSort::from(fragment_type),
-                SqlSort::from(direction)
+                nulls,
+            } => order_by(
+                &format!(
+                    "de.value_fragment_{}_{}",
+                    position,
+                    SqlSort::from(fragment_type)
+                ),
+                direction,
+                nulls,
+            ),
+            SortItem::Random { seed: Some(seed) } => format!("md5(de.uid::text || '{}')", seed),
+            SortItem::Random { seed: None } => "random()".into(),
+            SortItem::Relevance { key } => format!(
+                "(de.key = '{}') DESC, length(de.key) ASC, de.key ASC",
+                pg_escape(key.as_str())
             ),
         }
     }
@@ -278,19 +586,147 @@ impl From<SortItemDirection> for SqlSort {
     }
 }
 
-impl From<MgetEntries> for SqlWhere {
-    fn from(v: MgetEntries) -> SqlWhere {
-        format!(
-            "(address, key) IN ({})",
-            v.address_key_pairs
-                .into_iter()
-                .map(|entry| format!(
-                    "('{}', '{}')",
-                    pg_escape(entry.address.as_str()),
-                    pg_escape(entry.key.as_str())
-                ))
-                .collect::<Vec<_>>()
-                .join(",")
+impl From<SortNulls> for SqlSort {
+    fn from(v: SortNulls) -> SqlSort {
+        match v {
+            SortNulls::First => "NULLS FIRST".into(),
+            SortNulls::Last => "NULLS LAST".into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sort(json: &str) -> SqlSort {
+        serde_json::from_str::<RequestSort>(json).unwrap().into()
+    }
+
+    fn filter(json: &str) -> SqlWhere {
+        filter_with(
+            json,
+            &SqlContext {
+                md5_min_value_len: 256,
+            },
         )
     }
+
+    fn filter_with(json: &str, sql: &SqlContext) -> SqlWhere {
+        sql.filter(serde_json::from_str::<RequestFilter>(json).unwrap())
+    }
+
+    #[test]
+    fn value_json_compares_inner_field() {
+        assert_eq!(
+            filter(r#"{"value_json": {"path": ["meta", "name"], "value": "it's a \"name\""}}"#),
+            r#"(CASE WHEN value_string ~ '^\s*[\[{]' THEN value_string::jsonb #> ARRAY['meta','name'] END) = '"it''s a \"name\""'::jsonb"#
+        );
+    }
+
+    #[test]
+    fn in_decodes_binary_strings() {
+        // [1, 2, 3] as base64 and as hex, then [10, 11] as `base64:`-prefixed
+        assert_eq!(
+            filter(
+                r#"{"in": {"properties": [{"value": {"type": "binary"}}], "values": [["AQID"], ["0x010203"], ["base64:Cgs="]]}}"#
+            ),
+            "((value_binary) IN (('AQID'),('AQID'),('Cgs=')))"
+        );
+    }
+
+    #[test]
+    fn empty_combinators_add_no_condition() {
+        assert_eq!(filter(r#"{"and": []}"#), "");
+        assert_eq!(filter(r#"{"or": []}"#), "");
+        assert_eq!(
+            filter(r#"{"and": [{"or": []}, {"and": [{"and": []}]}]}"#),
+            ""
+        );
+    }
+
+    #[test]
+    fn single_child_combinators_are_unwrapped() {
+        assert_eq!(
+            filter(r#"{"and": [{"or": []}, {"or": [{"and": [{"address": {"value": "a"}}]}]}]}"#),
+            "address = 'a'"
+        );
+        assert_eq!(
+            filter(
+                r#"{"and": [{"address": {"value": "a"}}, {"and": []}, {"key": {"value": "k"}}]}"#
+            ),
+            "(address = 'a' AND key = 'k')"
+        );
+    }
+
+    #[test]
+    fn tautology_makes_disjunction_empty() {
+        assert_eq!(
+            filter(r#"{"or": [{"address": {"value": "a"}}, {"and": []}]}"#),
+            ""
+        );
+        assert_eq!(
+            filter(
+                r#"{"and": [{"key": {"value": "k"}}, {"or": [{"address": {"value": "a"}}, {"or": []}]}]}"#
+            ),
+            "key = 'k'"
+        );
+    }
+
+    #[test]
+    fn ilike_matches_wildcards_literally() {
+        assert_eq!(
+            filter(r#"{"key": {"value": "50%_Off!", "operation": "ilike"}}"#),
+            "key ILIKE '%50!%!_Off!!%' ESCAPE '!'"
+        );
+        assert_eq!(
+            filter(r#"{"key": {"value": "it's", "operation": "ilike"}}"#),
+            "key ILIKE '%it''s%' ESCAPE '!'"
+        );
+    }
+
+    #[test]
+    fn md5_only_for_long_values() {
+        let sql = SqlContext {
+            md5_min_value_len: 8,
+        };
+
+        assert_eq!(
+            filter_with(
+                r#"{"value": {"type": "string", "operation": "eq", "value": "short"}}"#,
+                &sql
+            ),
+            "value_string = 'short'"
+        );
+        assert_eq!(
+            filter_with(
+                r#"{"value": {"type": "string", "operation": "eq", "value": "long enough"}}"#,
+                &sql
+            ),
+            format!(
+                "value_string = 'long enough' AND md5(value_string) = '{:x}'",
+                md5("long enough")
+            )
+        );
+    }
+
+    #[test]
+    fn bignum_compares_beyond_i64() {
+        assert_eq!(
+            filter(
+                r#"{"value": {"type": "bignum", "operation": "gt", "value": "9223372036854775808"}}"#
+            ),
+            r"(CASE WHEN value_string ~ '^-?[0-9]+(\.[0-9]+)?$' THEN value_string::numeric END) > '9223372036854775808'::numeric"
+        );
+    }
+
+    #[test]
+    fn seeded_random_sort_is_deterministic() {
+        let seeded = sort(r#"[{"random": {"seed": 42}}]"#);
+
+        assert_eq!(seeded, "md5(de.uid::text || '42'),de.uid ASC");
+        assert_eq!(seeded, sort(r#"[{"random": {"seed": 42}}]"#));
+        assert_ne!(seeded, sort(r#"[{"random": {"seed": 43}}]"#));
+        assert_eq!(sort(r#"[{"random": {}}]"#), "random(),de.uid ASC");
+    }
 }
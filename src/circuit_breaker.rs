@@ -0,0 +1,129 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Fast-fails db calls after a series of consecutive failures,
+/// so that a recovering database is not hit by every incoming request.
+///
+/// Once `cooldown` has passed, a single call is let through as a probe:
+/// its success closes the circuit, its failure keeps the circuit open for another cooldown.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    state: Arc<Mutex<State>>,
+    max_failures: u32,
+    cooldown: Duration,
+}
+
+#[derive(Default)]
+struct State {
+    failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// `max_failures` = 0 disables the breaker.
+    pub fn new(max_failures: u32, cooldown: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::default())),
+            max_failures,
+            cooldown,
+        }
+    }
+
+    /// Returns the time left until the next probe when the circuit is open.
+    pub fn check(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        match state.opened_at {
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => {
+                Err(self.cooldown - opened_at.elapsed())
+            }
+            Some(_) => {
+                // let this call probe the db, the others keep failing fast meanwhile
+                state.opened_at = Some(Instant::now());
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn record<T, E>(&self, res: &Result<T, E>) {
+        if self.max_failures == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        match res {
+            Ok(_) => {
+                state.failures = 0;
+                state.opened_at = None;
+            }
+            Err(_) => {
+                state.failures = state.failures.saturating_add(1);
+                if state.failures >= self.max_failures {
+                    state.opened_at = Some(Instant::now());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOLDOWN: Duration = Duration::from_millis(20);
+
+    fn fail(breaker: &CircuitBreaker) {
+        breaker.record::<(), _>(&Err(()));
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(3, COOLDOWN);
+
+        fail(&breaker);
+        fail(&breaker);
+        breaker.record::<_, ()>(&Ok(()));
+        fail(&breaker);
+        fail(&breaker);
+        assert!(breaker.check().is_ok());
+
+        fail(&breaker);
+        let retry_after = breaker.check().unwrap_err();
+        assert!(retry_after <= COOLDOWN);
+    }
+
+    #[test]
+    fn recovers_after_successful_probe() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        fail(&breaker);
+        assert!(breaker.check().is_err());
+
+        std::thread::sleep(COOLDOWN);
+        // a single call probes the db, the others keep failing fast meanwhile
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_err());
+
+        breaker.record::<_, ()>(&Ok(()));
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn stays_open_after_failed_probe() {
+        let breaker = CircuitBreaker::new(1, COOLDOWN);
+        fail(&breaker);
+
+        std::thread::sleep(COOLDOWN);
+        assert!(breaker.check().is_ok());
+        fail(&breaker);
+        assert!(breaker.check().is_err());
+    }
+
+    #[test]
+    fn zero_max_failures_disables_breaker() {
+        let breaker = CircuitBreaker::new(0, COOLDOWN);
+        (0..10).for_each(|_| fail(&breaker));
+
+        assert!(breaker.check().is_ok());
+    }
+}
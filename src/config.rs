@@ -1,5 +1,9 @@
 use crate::error::Error;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// longer identifiers are truncated by postgres
+const PG_IDENTIFIER_MAX_LEN: usize = 63;
 
 fn default_port() -> u16 {
     8080
@@ -9,6 +13,18 @@ fn default_metrics_port() -> u16 {
     9090
 }
 
+fn default_db_breaker_failures() -> u32 {
+    5
+}
+
+fn default_db_breaker_cooldown_secs() -> u64 {
+    10
+}
+
+fn default_value_md5_min_len() -> usize {
+    256
+}
+
 fn default_pgport() -> u16 {
     5432
 }
@@ -17,12 +33,53 @@ fn default_pgpoolsize() -> u8 {
     4
 }
 
+fn default_pgschema() -> String {
+    "public".to_string()
+}
+
+fn default_pgmaxlifetime_secs() -> u64 {
+    30 * 60
+}
+
+fn default_pgidletimeout_secs() -> u64 {
+    5 * 60
+}
+
+fn default_pgconnectiontimeout_secs() -> u64 {
+    5
+}
+
+/// Groups of api routes, which can be disabled per deployment.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteGroup {
+    Search,
+    Mget,
+    Changes,
+    Aggregate,
+    Admin,
+}
+
 #[derive(Deserialize, Debug, Clone)]
 struct ConfigFlat {
     #[serde(default = "default_port")]
     port: u16,
     #[serde(default = "default_metrics_port")]
     metrics_port: u16,
+    admin_token: Option<String>,
+    #[serde(default = "default_db_breaker_failures")]
+    db_breaker_failures: u32,
+    #[serde(default = "default_db_breaker_cooldown_secs")]
+    db_breaker_cooldown_secs: u64,
+    max_response_bytes: Option<usize>,
+    rate_limit_per_sec: Option<u32>,
+    rate_limit_burst: Option<u32>,
+    #[serde(default)]
+    disabled_routes: Vec<RouteGroup>,
+    #[serde(default = "default_value_md5_min_len")]
+    value_md5_min_len: usize,
+    #[serde(default)]
+    pretty_responses: bool,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -35,18 +92,46 @@ struct PostgresConfigFlat {
     pub pgpassword: String,
     #[serde(default = "default_pgpoolsize")]
     pub pgpoolsize: u8,
+    #[serde(default = "default_pgschema")]
+    pub pgschema: String,
+    pub pginstance: Option<String>,
+    #[serde(default = "default_pgmaxlifetime_secs")]
+    pub pgmaxlifetime_secs: u64,
+    #[serde(default = "default_pgidletimeout_secs")]
+    pub pgidletimeout_secs: u64,
+    #[serde(default = "default_pgconnectiontimeout_secs")]
+    pub pgconnectiontimeout_secs: u64,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct TracingConfig {
     pub service_name_prefix: Option<String>,
     pub jaeger_agent_endpoint: Option<String>,
+    // share of the traces recorded, from 0 to 1, all of them when not set
+    pub sample_rate: Option<f64>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub port: u16,
     pub metrics_port: u16,
+    // admin routes are mounted only when the token is set
+    pub admin_token: Option<String>,
+    // consecutive db failures opening the circuit, 0 disables the breaker
+    pub db_breaker_failures: u32,
+    pub db_breaker_cooldown: Duration,
+    // soft size limit of search and mget responses, unlimited when not set
+    pub max_response_bytes: Option<usize>,
+    // requests per second of a single client, not limited when not set
+    pub rate_limit_per_sec: Option<u32>,
+    // defaults to the rate, also when 0
+    pub rate_limit_burst: Option<u32>,
+    // comma-separated, routes of these groups are not mounted and respond with 404
+    pub disabled_routes: Vec<RouteGroup>,
+    // string value filters of at least this many bytes also match by md5 of the value
+    pub value_md5_min_len: usize,
+    // allows `pretty=true`, indenting json responses for debugging
+    pub pretty_responses: bool,
     pub postgres: PostgresConfig,
     pub tracing: TracingConfig,
 }
@@ -59,6 +144,14 @@ pub struct PostgresConfig {
     pub user: String,
     pub password: String,
     pub pool_size: u8,
+    pub schema: String,
+    // appended to application_name of the db connections
+    pub instance: Option<String>,
+    // pooled connections are recycled after this time, regardless of their use
+    pub max_lifetime: Duration,
+    pub idle_timeout: Duration,
+    // wait for a free pooled connection, the request fails with 503 after it
+    pub connection_timeout: Duration,
 }
 
 impl From<PostgresConfigFlat> for PostgresConfig {
@@ -70,16 +163,91 @@ impl From<PostgresConfigFlat> for PostgresConfig {
             user: pgcf.pguser,
             password: pgcf.pgpassword,
             pool_size: pgcf.pgpoolsize,
+            schema: pgcf.pgschema,
+            instance: pgcf.pginstance,
+            max_lifetime: Duration::from_secs(pgcf.pgmaxlifetime_secs),
+            idle_timeout: Duration::from_secs(pgcf.pgidletimeout_secs),
+            connection_timeout: Duration::from_secs(pgcf.pgconnectiontimeout_secs),
         }
     }
 }
 
+// the schema is put into the queries as is, so it must be a plain identifier
+fn validate_schema(schema: &str) -> Result<(), Error> {
+    let valid = schema.len() <= PG_IDENTIFIER_MAX_LEN
+        && schema.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && schema
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidConfig(format!(
+            "PGSCHEMA must be an unquoted identifier, got `{}`",
+            schema
+        )))
+    }
+}
+
+// a ratio outside of the range would silently sample all traces or none
+fn validate_sample_rate(sample_rate: Option<f64>) -> Result<(), Error> {
+    match sample_rate {
+        Some(rate) if !(0.0..=1.0).contains(&rate) => Err(Error::InvalidConfig(format!(
+            "TRACING__SAMPLE_RATE must be between 0 and 1, got {}",
+            rate
+        ))),
+        _ => Ok(()),
+    }
+}
+
 pub fn load() -> Result<Config, Error> {
     let config_flat = envy::from_env::<ConfigFlat>()?;
+    let postgres: PostgresConfig = envy::from_env::<PostgresConfigFlat>()?.into();
+    validate_schema(&postgres.schema)?;
+    let tracing = envy::prefixed("TRACING__").from_env::<TracingConfig>()?;
+    validate_sample_rate(tracing.sample_rate)?;
     Ok(Config {
         port: config_flat.port,
         metrics_port: config_flat.metrics_port,
-        postgres: envy::from_env::<PostgresConfigFlat>()?.into(),
-        tracing: envy::prefixed("TRACING__").from_env::<TracingConfig>()?,
+        admin_token: config_flat.admin_token,
+        db_breaker_failures: config_flat.db_breaker_failures,
+        db_breaker_cooldown: Duration::from_secs(config_flat.db_breaker_cooldown_secs),
+        max_response_bytes: config_flat.max_response_bytes,
+        rate_limit_per_sec: config_flat.rate_limit_per_sec,
+        rate_limit_burst: config_flat.rate_limit_burst,
+        disabled_routes: config_flat.disabled_routes,
+        value_md5_min_len: config_flat.value_md5_min_len,
+        pretty_responses: config_flat.pretty_responses,
+        postgres,
+        tracing,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_must_be_an_identifier() {
+        assert!(validate_schema("public").is_ok());
+        assert!(validate_schema("tenant_2").is_ok());
+
+        assert!(validate_schema("").is_err());
+        assert!(validate_schema("2tenant").is_err());
+        assert!(validate_schema("tenant-a").is_err());
+        assert!(validate_schema("public.data_entries; --").is_err());
+        assert!(validate_schema(&"s".repeat(PG_IDENTIFIER_MAX_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn sample_rate_must_be_a_ratio() {
+        assert!(validate_sample_rate(None).is_ok());
+        assert!(validate_sample_rate(Some(0.0)).is_ok());
+        assert!(validate_sample_rate(Some(0.1)).is_ok());
+        assert!(validate_sample_rate(Some(1.0)).is_ok());
+
+        assert!(validate_sample_rate(Some(-0.1)).is_err());
+        assert!(validate_sample_rate(Some(1.5)).is_err());
+        assert!(validate_sample_rate(Some(f64::NAN)).is_err());
+    }
+}
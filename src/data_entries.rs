@@ -1,12 +1,25 @@
+use diesel::pg::Pg;
 use diesel::prelude::*;
-use diesel::sql_types::Integer;
+use diesel::row::NamedRow;
+use diesel::sql_types::{BigInt, Bool, Integer, Nullable, Text};
+use itertools::Itertools;
 use tokio::task::block_in_place;
 use tracing::{info_span, instrument};
 
+use crate::api::fields::{Field, Fields};
 use crate::api::historical::HistoricalRequestParams;
 use crate::api::parsing::AndFilter;
+use crate::api::parsing::DedupBy;
+use crate::api::parsing::GroupBy;
+use crate::api::parsing::GroupOrder;
+use crate::api::parsing::KeyFilter;
+use crate::api::parsing::KeyOperation;
 use crate::api::parsing::MgetEntries;
 use crate::api::parsing::RequestFilter;
+use crate::api::parsing::ValueFilter;
+use crate::api::parsing::FRAGMENT_POSITION_MAX;
+use crate::api::SqlContext;
+use crate::circuit_breaker::CircuitBreaker;
 use crate::db::PgPool;
 use crate::error::Error;
 use crate::schema::blocks_microblocks;
@@ -24,15 +37,60 @@ struct BlockMicroblock {
 
 #[derive(Clone, Debug, QueryableByName)]
 #[table_name = "data_entries"]
+struct DataEntryAddress {
+    address: String,
+}
+
+#[derive(Clone, Debug, QueryableByName)]
+struct Height {
+    #[sql_type = "Nullable<Integer>"]
+    height: Option<i32>,
+}
+
+#[derive(Clone, Debug, QueryableByName)]
+struct Exists {
+    #[sql_type = "Bool"]
+    exists: bool,
+}
+
+#[derive(Clone, Debug, QueryableByName)]
+struct Count {
+    #[sql_type = "BigInt"]
+    count: i64,
+}
+
+#[derive(Clone, Debug)]
+struct QueryPlan {
+    // json, read as text
+    plan: String,
+}
+
+// the column name is not an identifier, so the derive can't map it
+impl diesel::query_source::QueryableByName<Pg> for QueryPlan {
+    fn build<R: NamedRow<Pg>>(row: &R) -> diesel::deserialize::Result<Self> {
+        Ok(Self {
+            plan: row.get::<Text, _>("QUERY PLAN")?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, QueryableByName)]
+#[cfg_attr(test, derive(Default))]
+#[table_name = "data_entries"]
 pub struct DataEntry {
+    pub uid: i64,
     pub address: String,
     pub key: String,
-    #[sql_type = "Integer"]
-    pub height: i32,
+    // null when the block row is missing from the join, e.g. during a rollback
+    #[sql_type = "Nullable<Integer>"]
+    pub height: Option<i32>,
     pub value_binary: Option<Vec<u8>>,
     pub value_bool: Option<bool>,
     pub value_integer: Option<i64>,
     pub value_string: Option<String>,
+    // chars of string values, bytes of binary ones
+    #[sql_type = "Nullable<Integer>"]
+    pub value_size: Option<i32>,
     pub fragment_0_string: Option<String>,
     pub fragment_0_integer: Option<i64>,
     pub fragment_1_string: Option<String>,
@@ -79,6 +137,37 @@ pub struct DataEntry {
     pub value_fragment_10_integer: Option<i64>,
 }
 
+/// Value of an entry version, keyed by the version which superseded it.
+#[derive(Clone, Debug, QueryableByName)]
+#[table_name = "data_entries"]
+pub struct PreviousValue {
+    pub superseded_by: i64,
+    pub value_binary: Option<Vec<u8>>,
+    pub value_bool: Option<bool>,
+    pub value_integer: Option<i64>,
+    pub value_string: Option<String>,
+}
+
+/// Number of versions an entry had, deletions included.
+#[derive(Clone, Debug, QueryableByName)]
+#[table_name = "data_entries"]
+pub struct VersionCount {
+    pub address: String,
+    pub key: String,
+    #[sql_type = "BigInt"]
+    pub version_count: i64,
+}
+
+/// Height at which an entry got its first version.
+#[derive(Clone, Debug, QueryableByName)]
+#[table_name = "data_entries"]
+pub struct FirstHeight {
+    pub address: String,
+    pub key: String,
+    #[sql_type = "Integer"]
+    pub first_height: i32,
+}
+
 const MAX_UID: i64 = std::i64::MAX - 1;
 
 const BASE_WHERE: &str = " WHERE (de.value_binary IS NOT NULL OR de.value_bool IS NOT NULL OR de.value_integer IS NOT NULL OR de.value_string IS NOT NULL) ";
@@ -97,87 +186,439 @@ de.value_fragment_6_string, de.value_fragment_6_integer, de.value_fragment_7_str
 de.value_fragment_8_string, de.value_fragment_8_integer, de.value_fragment_9_string, de.value_fragment_9_integer, \
 de.value_fragment_10_string, de.value_fragment_10_integer ";
 
+const VALUE_COLUMNS: [(&str, &str); 4] = [
+    ("value_binary", "bytea"),
+    ("value_bool", "bool"),
+    ("value_integer", "int8"),
+    ("value_string", "varchar"),
+];
+
+/// Selects the requested fields from `de`, replacing the rest with typed NULLs
+/// so the rows still map onto `DataEntry`.
+fn query_fields(fields: &Fields, height_column: &str) -> String {
+    let mut columns = vec![
+        "de.uid".to_string(),
+        "de.address".to_string(),
+        "de.key".to_string(),
+        format!("{} as height", height_column),
+    ];
+
+    let with_value = fields.contains(&Field::Value);
+    columns.extend(
+        VALUE_COLUMNS
+            .iter()
+            .map(|(column, sql_type)| project_column(column, sql_type, with_value)),
+    );
+
+    columns.push(if fields.contains(&Field::ValueSize) {
+        "coalesce(length(de.value_string), octet_length(de.value_binary)) as value_size".to_string()
+    } else {
+        "NULL::int4 as value_size".to_string()
+    });
+
+    let with_fragments = fields.contains(&Field::Fragments);
+    for prefix in &["fragment", "value_fragment"] {
+        for position in 0..=FRAGMENT_POSITION_MAX {
+            let string_column = format!("{}_{}_string", prefix, position);
+            let integer_column = format!("{}_{}_integer", prefix, position);
+            columns.push(project_column(&string_column, "varchar", with_fragments));
+            columns.push(project_column(&integer_column, "int8", with_fragments));
+        }
+    }
+
+    columns.join(", ")
+}
+
+fn project_column(column: &str, sql_type: &str, selected: bool) -> String {
+    if selected {
+        format!("de.{}", column)
+    } else {
+        format!("NULL::{} as {}", sql_type, column)
+    }
+}
+
 #[derive(Clone)]
 pub struct Repo {
     pg_pool: PgPool,
+    schema: String,
+    breaker: CircuitBreaker,
+    sql: SqlContext,
 }
 
 impl Repo {
-    pub fn new(pg_pool: PgPool) -> Self {
-        Self { pg_pool }
+    pub fn new(pg_pool: PgPool, schema: String, breaker: CircuitBreaker, sql: SqlContext) -> Self {
+        Self {
+            pg_pool,
+            schema,
+            breaker,
+            sql,
+        }
+    }
+
+    /// Runs a blocking db call through the circuit breaker.
+    fn blocking<T>(&self, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+        self.breaker.check().map_err(Error::CircuitOpen)?;
+        let res = block_in_place(f);
+        self.breaker.record(&res);
+        res
     }
 
-    #[instrument(level = "trace", skip(self, filter, sort, limit, offset))]
+    /// Runs empty selects over every column the queries rely on,
+    /// so a schema mismatch is reported at startup instead of at the first request.
+    pub async fn check_schema(&self) -> Result<(), Error> {
+        block_in_place(|| {
+            let conn = &self.pg_pool.get()?;
+
+            let checks = vec![
+                (
+                    "data_entries",
+                    format!(
+                        "select {}, de.superseded_by, de.block_uid, de.transaction_id FROM {schema}.data_entries de LEFT JOIN {schema}.blocks_microblocks bm ON bm.uid = de.block_uid LIMIT 0",
+                        BASE_QUERY_FIELDS,
+                        schema = self.schema
+                    ),
+                ),
+                (
+                    "data_entries_history_keys",
+                    format!(
+                        "select data_entry_uid, address, \"key\", height, block_timestamp FROM {}.data_entries_history_keys LIMIT 0",
+                        self.schema
+                    ),
+                ),
+            ];
+
+            checks.into_iter().try_for_each(|(table, sql)| {
+                diesel::sql_query(&sql)
+                    .execute(conn)
+                    .map(|_| ())
+                    .map_err(|err| Error::SchemaMismatch(format!("{}: {}", table, err)))
+            })
+        })
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    pub async fn get_earliest_indexed_height(&self) -> Result<Option<i32>, Error> {
+        self.blocking(|| {
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let sql = format!(
+                "select min(height) as height FROM {}.blocks_microblocks",
+                self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .get_result::<Height>(conn)
+                .map(|r| r.height)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    pub async fn get_last_indexed_height(&self) -> Result<Option<i32>, Error> {
+        self.blocking(|| {
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let sql = format!(
+                "select max(height) as height FROM {}.blocks_microblocks",
+                self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .get_result::<Height>(conn)
+                .map(|r| r.height)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    #[instrument(level = "trace", skip(self, filter, sort, limit, offset, fields))]
     pub async fn search_data_entries(
         &self,
         filter: Option<RequestFilter>,
         sort: Option<impl Into<SqlSort>>,
+        dedup_by: Option<&DedupBy>,
         limit: u64,
         offset: u64,
+        fields: &Fields,
     ) -> Result<Vec<DataEntry>, Error> {
-        block_in_place(|| {
-            let mut addr_key_cnt = 0;
-
-            match &filter {
-                Some(RequestFilter::And(AndFilter(and_filter))) => {
-                    and_filter.iter().for_each(|f| match f {
-                        RequestFilter::Address(_) | RequestFilter::Key(_) => {
-                            addr_key_cnt += 1;
-                        }
-                        _ => {}
-                    });
-                }
-                _ => {}
-            };
+        self.blocking(|| {
+            let sql = self.search_sql(filter, sort, dedup_by, limit, offset, fields);
+
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            diesel::sql_query(&sql)
+                .bind::<diesel::sql_types::BigInt, _>(MAX_UID)
+                .get_results::<DataEntry>(conn)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    /// Runs the search query with `EXPLAIN ANALYZE` and returns its plan, never the rows.
+    #[instrument(level = "trace", skip(self, filter, sort, limit, offset, fields))]
+    pub async fn explain_search_data_entries(
+        &self,
+        filter: Option<RequestFilter>,
+        sort: Option<impl Into<SqlSort>>,
+        dedup_by: Option<&DedupBy>,
+        limit: u64,
+        offset: u64,
+        fields: &Fields,
+    ) -> Result<serde_json::Value, Error> {
+        self.blocking(|| {
+            let sql = self.search_sql(filter, sort, dedup_by, limit, offset, fields);
+
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let plan =
+                diesel::sql_query(format!("EXPLAIN (ANALYZE, BUFFERS, FORMAT JSON) {}", sql))
+                    .bind::<diesel::sql_types::BigInt, _>(MAX_UID)
+                    .get_result::<QueryPlan>(conn)
+                    .map_err(|err| Error::DbError(err))?;
+
+            serde_json::from_str(&plan.plan).map_err(|err| Error::InvalidMessage(err.to_string()))
+        })
+    }
+
+    fn search_sql(
+        &self,
+        filter: Option<RequestFilter>,
+        sort: Option<impl Into<SqlSort>>,
+        dedup_by: Option<&DedupBy>,
+        limit: u64,
+        offset: u64,
+        fields: &Fields,
+    ) -> String {
+        let mut addr_key_cnt = 0;
+
+        match &filter {
+            Some(RequestFilter::And(AndFilter(and_filter))) => {
+                and_filter.iter().for_each(|f| match f {
+                    RequestFilter::Address(_)
+                    | RequestFilter::Key(KeyFilter {
+                        operation: KeyOperation::Eq,
+                        ..
+                    }) => {
+                        addr_key_cnt += 1;
+                    }
+                    _ => {}
+                });
+            }
+            _ => {}
+        };
+
+        let mut query_where_string: String = filter.map_or("".to_string(), |f| self.sql.filter(f));
+
+        if query_where_string.len() > 0 {
+            query_where_string = format!("AND {}", query_where_string);
+        }
+
+        let mut query_sort_string: String = sort.map_or("de.uid asc".to_string(), |s| s.into());
+
+        if query_sort_string.len() > 0 {
+            query_sort_string = format!("ORDER BY {}", query_sort_string);
+        }
+
+        // if we have RequestFilter::Address and RequestFilter::Key in search conditions
+        // then skip substitution ORDER BY and LIMIT ... OFFSET parts in inner subquery
+        // to avoid postgres performance issue
+        // values by this filters are unique in table with superseded_by = $1 condition
+        let (inner_query_sort_string, inner_limit_offset) = if addr_key_cnt >= 2 {
+            ("".into(), "".into())
+        } else {
+            (
+                query_sort_string.clone(),
+                format!("LIMIT {} OFFSET {}", limit, offset),
+            )
+        };
+
+        let entries_filtered = format!(
+            "{schema}.data_entries de {} AND de.superseded_by = $1 {}",
+            BASE_WHERE,
+            query_where_string,
+            schema = self.schema
+        );
+
+        // distinct on requires its columns to lead the ORDER BY,
+        // so the requested sort is applied on top of the deduplicated entries
+        let entries_source = match dedup_by {
+            Some(dedup_by) => {
+                let dedup_columns = match dedup_by {
+                    DedupBy::Key => "de.key",
+                    DedupBy::AddressKey => "de.address, de.key",
+                };
+                format!(
+                    "(select distinct on ({columns}) de.* FROM {} ORDER BY {columns}, de.uid DESC) de",
+                    entries_filtered,
+                    columns = dedup_columns
+                )
+            }
+            None => entries_filtered,
+        };
+
+        format!(
+            "
+            with entries_uids as (
+                select de.uid FROM {} {} {}
+            ),
+            entries_data as (
+                select {}
+                FROM {schema}.data_entries de
+                LEFT JOIN {schema}.blocks_microblocks bm ON bm.uid = de.block_uid
+                WHERE de.uid in (select uid from entries_uids)
+            )
+            select {} from entries_data de {}
+        ",
+            entries_source,
+            inner_query_sort_string,
+            inner_limit_offset,
+            BASE_QUERY_FIELDS,
+            query_fields(fields, "de.height"),
+            query_sort_string,
+            schema = self.schema
+        )
+    }
+
+    #[instrument(level = "trace", skip(self, filter))]
+    pub async fn exists_data_entries(&self, filter: Option<RequestFilter>) -> Result<bool, Error> {
+        self.blocking(|| {
+            let sql = self.exists_sql(filter);
+
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
 
-            let mut query_where_string: String = filter.map_or("".to_string(), |f| f.into());
+            diesel::sql_query(&sql)
+                .bind::<diesel::sql_types::BigInt, _>(MAX_UID)
+                .get_result::<Exists>(conn)
+                .map(|r| r.exists)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    fn exists_sql(&self, filter: Option<RequestFilter>) -> String {
+        let mut query_where_string: String = filter.map_or("".to_string(), |f| self.sql.filter(f));
+
+        if query_where_string.len() > 0 {
+            query_where_string = format!("AND {}", query_where_string);
+        }
+
+        format!(
+            "select exists (select 1 FROM {schema}.data_entries de {} AND de.superseded_by = $1 {}) as exists",
+            BASE_WHERE,
+            query_where_string,
+            schema = self.schema
+        )
+    }
+
+    #[instrument(level = "trace", skip(self, filter))]
+    pub async fn search_addresses(
+        &self,
+        filter: Option<RequestFilter>,
+        limit: u64,
+        offset: u64,
+    ) -> Result<Vec<String>, Error> {
+        self.blocking(|| {
+            let mut query_where_string: String = filter.map_or("".to_string(), |f| self.sql.filter(f));
 
             if query_where_string.len() > 0 {
                 query_where_string = format!("AND {}", query_where_string);
             }
 
-            let mut query_sort_string: String = sort.map_or("de.uid asc".to_string(), |s| s.into());
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
 
-            if query_sort_string.len() > 0 {
-                query_sort_string = format!("ORDER BY {}", query_sort_string);
+            let sql = format!(
+                "select distinct de.address FROM {schema}.data_entries de {} AND de.superseded_by = $1 {} ORDER BY de.address LIMIT {} OFFSET {}",
+                BASE_WHERE,
+                query_where_string,
+                limit,
+                offset,
+                schema = self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .bind::<diesel::sql_types::BigInt, _>(MAX_UID)
+                .get_results::<DataEntryAddress>(conn)
+                .map(|rows| rows.into_iter().map(|r| r.address).collect())
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    #[instrument(level = "trace", skip(self, filter))]
+    pub async fn count_addresses(&self, filter: Option<RequestFilter>) -> Result<i64, Error> {
+        self.blocking(|| {
+            let mut query_where_string: String = filter.map_or("".to_string(), |f| self.sql.filter(f));
+
+            if query_where_string.len() > 0 {
+                query_where_string = format!("AND {}", query_where_string);
             }
 
-            // if we have RequestFilter::Address and RequestFilter::Key in search conditions
-            // then skip substitution ORDER BY and LIMIT ... OFFSET parts in inner subquery
-            // to avoid postgres performance issue
-            // values by this filters are unique in table with superseded_by = $1 condition
-            let (inner_query_sort_string, inner_limit_offset) = if addr_key_cnt >= 2 {
-                ("".into(), "".into())
-            } else {
-                (
-                    query_sort_string.clone(),
-                    format!("LIMIT {} OFFSET {}", limit, offset),
-                )
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let sql = format!(
+                "select count(distinct de.address) as count FROM {schema}.data_entries de {} AND de.superseded_by = $1 {}",
+                BASE_WHERE,
+                query_where_string,
+                schema = self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .bind::<diesel::sql_types::BigInt, _>(MAX_UID)
+                .get_result::<Count>(conn)
+                .map(|r| r.count)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    /// Returns the latest entry of every group of the address keys.
+    #[instrument(level = "trace", skip(self, fields))]
+    pub async fn grouped_latest(
+        &self,
+        address: &str,
+        group_by: &GroupBy,
+        order: &GroupOrder,
+        limit: Option<u64>,
+        fields: &Fields,
+    ) -> Result<Vec<DataEntry>, Error> {
+        self.blocking(|| {
+            let group_column = match group_by {
+                GroupBy::Fragment0 => "de.fragment_0_string",
             };
 
+            // distinct on requires the group column to lead the inner ORDER BY,
+            // so the groups are ordered in the outer query
+            let order_string = match order {
+                GroupOrder::Group => "g.group_value ASC",
+                GroupOrder::ValueAsc => "g.value_integer_order ASC NULLS LAST, g.group_value ASC",
+                GroupOrder::ValueDesc => "g.value_integer_order DESC NULLS LAST, g.group_value ASC",
+            };
+
+            let limit_string = limit.map_or("".to_string(), |limit| format!("LIMIT {}", limit));
+
             let _g0 = info_span!("db_conn").entered();
             let conn = &self.pg_pool.get()?;
             let _g1 = info_span!("db_query").entered();
 
             let sql = format!(
-                "
-                with entries_uids as (
-                    select de.uid FROM data_entries de {} AND de.superseded_by = $1 {} {} {}
-                ),
-                entries_data as (
-                    select {}
-                    FROM data_entries de
-                    LEFT JOIN blocks_microblocks bm ON bm.uid = de.block_uid
-                    WHERE de.uid in (select uid from entries_uids)
-                )
-                select * from entries_data de {}
-            ",
+                "select g.* from (
+                    select distinct on ({group}) {}, {group} as group_value, de.value_integer as value_integer_order FROM {schema}.data_entries de LEFT JOIN {schema}.blocks_microblocks bm ON bm.uid = de.block_uid {} AND de.superseded_by = $1 AND de.address = '{}' AND {group} IS NOT NULL ORDER BY {group}, de.uid DESC
+                ) g ORDER BY {} {}",
+                query_fields(fields, "bm.height"),
                 BASE_WHERE,
-                query_where_string,
-                inner_query_sort_string,
-                inner_limit_offset,
-                BASE_QUERY_FIELDS,
-                query_sort_string
+                pg_escape(address),
+                order_string,
+                limit_string,
+                group = group_column,
+                schema = self.schema
             );
 
             diesel::sql_query(&sql)
@@ -187,14 +628,336 @@ impl Repo {
         })
     }
 
-    #[instrument(level = "trace", skip(self, filter, historical_filter))]
+    /// Returns every entry version set by the transaction, deletions and superseded ones included.
+    #[instrument(level = "trace", skip(self, fields))]
+    pub async fn entries_by_transaction_id(
+        &self,
+        transaction_id: &str,
+        limit: u64,
+        offset: u64,
+        fields: &Fields,
+    ) -> Result<Vec<DataEntry>, Error> {
+        self.blocking(|| {
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let sql = format!(
+                "select {} FROM {schema}.data_entries de LEFT JOIN {schema}.blocks_microblocks bm ON bm.uid = de.block_uid WHERE de.transaction_id = $1 ORDER BY de.uid LIMIT {} OFFSET {}",
+                query_fields(fields, "bm.height"),
+                limit,
+                offset,
+                schema = self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .bind::<diesel::sql_types::Text, _>(transaction_id)
+                .get_results::<DataEntry>(conn)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    /// Returns every entry version recorded in the block, deletions and superseded ones included.
+    #[instrument(level = "trace", skip(self, fields))]
+    pub async fn entries_by_block_uid(
+        &self,
+        block_uid: i64,
+        limit: u64,
+        offset: u64,
+        fields: &Fields,
+    ) -> Result<Vec<DataEntry>, Error> {
+        self.blocking(|| {
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let sql = format!(
+                "select {} FROM {schema}.data_entries de LEFT JOIN {schema}.blocks_microblocks bm ON bm.uid = de.block_uid WHERE de.block_uid = $1 ORDER BY de.uid LIMIT {} OFFSET {}",
+                query_fields(fields, "bm.height"),
+                limit,
+                offset,
+                schema = self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .bind::<diesel::sql_types::BigInt, _>(block_uid)
+                .get_results::<DataEntry>(conn)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    /// Returns the entry versions recorded within the time window, in the order they were applied,
+    /// following the version with the `after_uid` cursor.
+    #[instrument(level = "trace", skip(self, fields))]
+    pub async fn changes_by_timestamp(
+        &self,
+        since_timestamp: i64,
+        until_timestamp: Option<i64>,
+        after_uid: Option<i64>,
+        limit: u64,
+        fields: &Fields,
+    ) -> Result<Vec<DataEntry>, Error> {
+        self.blocking(|| {
+            let sql = self.changes_sql(until_timestamp.is_some(), after_uid, limit, fields);
+
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let query =
+                diesel::sql_query(&sql).bind::<diesel::sql_types::BigInt, _>(since_timestamp);
+            match until_timestamp {
+                Some(until_timestamp) => query
+                    .bind::<diesel::sql_types::BigInt, _>(until_timestamp)
+                    .get_results::<DataEntry>(conn),
+                None => query.get_results::<DataEntry>(conn),
+            }
+            .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    // keyset pagination, so that pages don't shift when new versions are indexed
+    fn changes_sql(
+        &self,
+        with_until: bool,
+        after_uid: Option<i64>,
+        limit: u64,
+        fields: &Fields,
+    ) -> String {
+        let until_condition = if with_until {
+            "AND h.block_timestamp < to_timestamp($2)"
+        } else {
+            ""
+        };
+
+        let after_condition = match after_uid {
+            Some(uid) => format!(
+                "AND (h.block_timestamp, h.data_entry_uid) > (select c.block_timestamp, c.data_entry_uid FROM {}.data_entries_history_keys c WHERE c.data_entry_uid = {})",
+                self.schema, uid
+            ),
+            None => "".to_string(),
+        };
+
+        format!(
+            "select {} FROM {schema}.data_entries_history_keys h JOIN {schema}.data_entries de ON de.uid = h.data_entry_uid WHERE h.block_timestamp >= to_timestamp($1) {} {} ORDER BY h.block_timestamp, h.data_entry_uid LIMIT {}",
+            query_fields(fields, "h.height"),
+            until_condition,
+            after_condition,
+            limit,
+            schema = self.schema
+        )
+    }
+
+    /// Tells whether the version is in the change feed, so that its uid is a valid cursor.
+    #[instrument(level = "trace", skip(self))]
+    pub async fn change_exists(&self, uid: i64) -> Result<bool, Error> {
+        self.blocking(|| {
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let sql = format!(
+                "select exists (select 1 FROM {}.data_entries_history_keys WHERE data_entry_uid = $1) as exists",
+                self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .bind::<diesel::sql_types::BigInt, _>(uid)
+                .get_result::<Exists>(conn)
+                .map(|r| r.exists)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    /// Returns the versions of the key, newest first, older than the `before_uid` cursor.
+    #[instrument(level = "trace", skip(self, fields))]
+    pub async fn key_history(
+        &self,
+        address: &str,
+        key: &str,
+        before_uid: Option<i64>,
+        limit: u64,
+        fields: &Fields,
+    ) -> Result<Vec<DataEntry>, Error> {
+        self.blocking(|| {
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let sql = format!(
+                "select {} FROM {schema}.data_entries de LEFT JOIN {schema}.blocks_microblocks bm ON bm.uid = de.block_uid WHERE de.address = $1 AND de.key = $2 AND de.uid < $3 ORDER BY de.uid DESC LIMIT {}",
+                query_fields(fields, "bm.height"),
+                limit,
+                schema = self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .bind::<diesel::sql_types::Text, _>(address)
+                .bind::<diesel::sql_types::Text, _>(key)
+                .bind::<diesel::sql_types::BigInt, _>(before_uid.unwrap_or(i64::MAX))
+                .get_results::<DataEntry>(conn)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    /// Returns the heights at which the key was set to a value matching the filter, ascending.
+    #[instrument(level = "trace", skip(self, filter))]
+    pub async fn value_ever_equaled(
+        &self,
+        address: &str,
+        key: &str,
+        filter: ValueFilter,
+        from_height: Option<i32>,
+        to_height: Option<i32>,
+        limit: u64,
+    ) -> Result<Vec<i32>, Error> {
+        self.blocking(|| {
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            // value columns are taken from de, the history keys only have the address and key
+            let sql = format!(
+                "select distinct h.height FROM {schema}.data_entries_history_keys h JOIN {schema}.data_entries de ON de.uid = h.data_entry_uid WHERE h.address = $1 AND h.key = $2 AND h.height >= $3 AND h.height <= $4 AND ({}) ORDER BY h.height LIMIT {}",
+                self.sql.value(filter),
+                limit,
+                schema = self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .bind::<diesel::sql_types::Text, _>(address)
+                .bind::<diesel::sql_types::Text, _>(key)
+                .bind::<Integer, _>(from_height.unwrap_or(0))
+                .bind::<Integer, _>(to_height.unwrap_or(i32::MAX))
+                .get_results::<Height>(conn)
+                .map(|heights| heights.into_iter().filter_map(|h| h.height).collect())
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    /// Returns the values the given entry versions replaced,
+    /// versions which were the first ones of their keys have none.
+    #[instrument(level = "trace", skip(self, uids))]
+    pub async fn previous_values(&self, uids: &[i64]) -> Result<Vec<PreviousValue>, Error> {
+        if uids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        self.blocking(|| {
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let sql = format!(
+                "select p.superseded_by, p.value_binary, p.value_bool, p.value_integer, p.value_string FROM {schema}.data_entries p WHERE p.superseded_by in ({})",
+                uids.iter().join(","),
+                schema = self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .get_results::<PreviousValue>(conn)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    /// Counts the versions of the entries, entries without any are missing from the result.
+    #[instrument(level = "trace", skip(self, address_key_pairs))]
+    pub async fn version_counts(
+        &self,
+        address_key_pairs: &[(String, String)],
+    ) -> Result<Vec<VersionCount>, Error> {
+        if address_key_pairs.is_empty() {
+            return Ok(vec![]);
+        }
+
+        self.blocking(|| {
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let sql = format!(
+                "select address, \"key\", count(*) as version_count FROM {schema}.data_entries_history_keys WHERE (address, \"key\") in ({}) GROUP BY address, \"key\"",
+                address_key_pairs
+                    .iter()
+                    .map(|(address, key)| format!("('{}', '{}')", pg_escape(address), pg_escape(key)))
+                    .join(","),
+                schema = self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .get_results::<VersionCount>(conn)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    /// Finds the heights of the first versions of the entries, entries without any are missing from the result.
+    #[instrument(level = "trace", skip(self, address_key_pairs))]
+    pub async fn first_heights(
+        &self,
+        address_key_pairs: &[(String, String)],
+    ) -> Result<Vec<FirstHeight>, Error> {
+        if address_key_pairs.is_empty() {
+            return Ok(vec![]);
+        }
+
+        self.blocking(|| {
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let sql = format!(
+                "select address, \"key\", min(height)::int4 as first_height FROM {schema}.data_entries_history_keys WHERE (address, \"key\") in ({}) GROUP BY address, \"key\"",
+                address_key_pairs
+                    .iter()
+                    .map(|(address, key)| format!("('{}', '{}')", pg_escape(address), pg_escape(key)))
+                    .join(","),
+                schema = self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .get_results::<FirstHeight>(conn)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    /// Returns the entry versions created at the height, deletions included.
+    #[instrument(level = "trace", skip(self, fields))]
+    pub async fn changes_at_height(
+        &self,
+        height: i32,
+        limit: u64,
+        offset: u64,
+        fields: &Fields,
+    ) -> Result<Vec<DataEntry>, Error> {
+        self.blocking(|| {
+            let _g0 = info_span!("db_conn").entered();
+            let conn = &self.pg_pool.get()?;
+            let _g1 = info_span!("db_query").entered();
+
+            let sql = format!(
+                "select {} FROM {schema}.blocks_microblocks bm JOIN {schema}.data_entries de ON de.block_uid = bm.uid WHERE bm.height = $1 ORDER BY de.uid LIMIT {} OFFSET {}",
+                query_fields(fields, "bm.height"),
+                limit,
+                offset,
+                schema = self.schema
+            );
+
+            diesel::sql_query(&sql)
+                .bind::<Integer, _>(height)
+                .get_results::<DataEntry>(conn)
+                .map_err(|err| Error::DbError(err))
+        })
+    }
+
+    #[instrument(level = "trace", skip(self, entries, uids, fields))]
     pub async fn mget_data_entries(
         &self,
-        filter: impl Into<SqlWhere>,
-        historical_filter: String,
+        entries: MgetEntries,
+        uids: &[i64],
+        fields: &Fields,
     ) -> Result<Vec<DataEntry>, Error> {
-        block_in_place(|| {
-            let query_filter_string: String = filter.into();
+        self.blocking(|| {
+            let query_filter_string = self.sql.mget(entries);
 
             if query_filter_string.len() > 0 {
                 let _g0 = info_span!("db_conn").entered();
@@ -202,14 +965,24 @@ impl Repo {
                 let _g1 = info_span!("db_query").entered();
 
                 let sql = format!(
-                    "select {} FROM data_entries de LEFT JOIN blocks_microblocks bm ON bm.uid = de.block_uid {} AND ({}) {}",
-                    BASE_QUERY_FIELDS, BASE_WHERE, query_filter_string, historical_filter
+                    "select {} FROM {schema}.data_entries de LEFT JOIN {schema}.blocks_microblocks bm ON bm.uid = de.block_uid {} AND ({})",
+                    query_fields(fields, "bm.height"),
+                    BASE_WHERE,
+                    query_filter_string,
+                    schema = self.schema
                 );
 
-                diesel::sql_query(&sql)
-                    .bind::<diesel::sql_types::BigInt, _>(MAX_UID)
-                    .get_results::<DataEntry>(conn)
-                    .map_err(|err| Error::DbError(err))
+                // latest entries are the not yet superseded ones,
+                // historical entries are pinned by the uids resolved for the requested height
+                if uids.is_empty() {
+                    diesel::sql_query(format!("{} AND de.superseded_by = $1", sql))
+                        .bind::<diesel::sql_types::BigInt, _>(MAX_UID)
+                        .get_results::<DataEntry>(conn)
+                } else {
+                    diesel::sql_query(format!("{} AND de.uid in ({})", sql, uids.iter().join(",")))
+                        .get_results::<DataEntry>(conn)
+                }
+                .map_err(|err| Error::DbError(err))
             } else {
                 Ok(vec![])
             }
@@ -225,7 +998,7 @@ impl Repo {
             return Ok(vec![]);
         }
 
-        block_in_place(|| {
+        self.blocking(|| {
             let mut uids = vec![];
             let mut sqls: Vec<String> = vec![];
 
@@ -233,9 +1006,10 @@ impl Repo {
                     if hp.height.is_some() {
                         sqls.push(
                             format!(
-                                "(select data_entry_uid as uid from data_entries_history_keys where address = '{}' and \"key\" = '{}' and height <= $1 order by height desc, data_entry_uid desc limit 1)",
+                                "(select data_entry_uid as uid from {schema}.data_entries_history_keys where address = '{}' and \"key\" = '{}' and height <= $1 order by height desc, data_entry_uid desc limit 1)",
                                 pg_escape(e.address.as_str()),
                                 pg_escape(e.key.as_str()),
+                                schema = self.schema,
                             )
                         );
                     }
@@ -243,9 +1017,10 @@ impl Repo {
                     if hp.block_timestamp.is_some() {
                         sqls.push(
                             format!(
-                                "(select data_entry_uid as uid from data_entries_history_keys where address = '{}' and \"key\" = '{}' and block_timestamp <= to_timestamp($1) order by block_timestamp desc, data_entry_uid desc limit 1)",
+                                "(select data_entry_uid as uid from {schema}.data_entries_history_keys where address = '{}' and \"key\" = '{}' and block_timestamp <= to_timestamp($1) order by block_timestamp desc, data_entry_uid desc limit 1)",
                                 pg_escape(e.address.as_str()),
                                 pg_escape(e.key.as_str()),
+                                schema = self.schema,
                             )
                         );
                     }
@@ -280,3 +1055,72 @@ impl Repo {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::parsing::AddressFilter;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use std::time::Duration;
+
+    // the pool never connects, queries are only built
+    fn repo(schema: &str) -> Repo {
+        let manager = ConnectionManager::new("postgres://localhost/state");
+        let pg_pool = Pool::builder().build_unchecked(manager);
+        Repo::new(
+            pg_pool,
+            schema.to_string(),
+            CircuitBreaker::new(0, Duration::from_secs(1)),
+            SqlContext {
+                md5_min_value_len: 256,
+            },
+        )
+    }
+
+    #[test]
+    fn exists_sql_fetches_no_rows() {
+        let filter = RequestFilter::Address(AddressFilter {
+            value: "3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP".into(),
+        });
+
+        let sql = repo("public").exists_sql(Some(filter));
+
+        assert!(sql.starts_with("select exists (select 1 FROM public.data_entries de"));
+        assert!(sql.contains("AND address = '3P5Bfd58PPfNvBM2Hy8QfbcDqMeNtzg7KfP'"));
+        assert!(!sql.contains("LIMIT"));
+    }
+
+    #[test]
+    fn queries_resolve_in_configured_schema() {
+        let repo = repo("tenant_a");
+
+        let search = repo.search_sql(None, None::<SqlSort>, None, 10, 0, &Fields::default());
+        let exists = repo.exists_sql(None);
+
+        for sql in &[&search, &exists] {
+            assert!(sql.contains("tenant_a.data_entries de"));
+            assert!(!sql.contains("public."));
+        }
+        assert!(search.contains("LEFT JOIN tenant_a.blocks_microblocks bm"));
+    }
+
+    #[test]
+    fn changes_are_bounded_by_window() {
+        let sql = repo("public").changes_sql(true, None, 101, &Fields::default());
+
+        assert!(sql.contains("WHERE h.block_timestamp >= to_timestamp($1)"));
+        assert!(sql.contains("AND h.block_timestamp < to_timestamp($2)"));
+        assert!(sql.ends_with("ORDER BY h.block_timestamp, h.data_entry_uid LIMIT 101"));
+    }
+
+    #[test]
+    fn changes_page_after_cursor() {
+        let sql = repo("public").changes_sql(false, Some(42), 101, &Fields::default());
+
+        assert!(!sql.contains("$2"));
+        assert!(sql.contains(
+            "AND (h.block_timestamp, h.data_entry_uid) > (select c.block_timestamp, c.data_entry_uid FROM public.data_entries_history_keys c WHERE c.data_entry_uid = 42)"
+        ));
+        assert!(!sql.contains("OFFSET"));
+    }
+}
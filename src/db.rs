@@ -1,11 +1,36 @@
+use diesel::connection::SimpleConnection;
 use diesel::pg::PgConnection;
-use diesel::r2d2::{ConnectionManager, Pool};
-use std::time::Duration;
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection, Pool};
 
-use crate::{config::PostgresConfig, error::Error};
+use crate::{config::PostgresConfig, error::Error, text_utils::pg_escape};
 
 pub type PgPool = Pool<ConnectionManager<PgConnection>>;
 
+/// Sets `application_name`, so that the service connections can be told apart in `pg_stat_activity`,
+/// and `standard_conforming_strings`, which the escaping of sql literals relies on.
+#[derive(Debug)]
+struct SessionSettings {
+    application_name: String,
+}
+
+impl CustomizeConnection<PgConnection, r2d2::Error> for SessionSettings {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), r2d2::Error> {
+        conn.batch_execute(&format!(
+            "SET application_name = '{}'; SET standard_conforming_strings = on",
+            pg_escape(&self.application_name)
+        ))
+        .map_err(r2d2::Error::QueryError)
+    }
+}
+
+fn application_name(instance: &Option<String>) -> String {
+    let name = format!("state-service/{}", env!("CARGO_PKG_VERSION"));
+    match instance {
+        Some(instance) => format!("{}/{}", name, instance),
+        None => name,
+    }
+}
+
 pub fn pool(config: &PostgresConfig) -> Result<PgPool, Error> {
     let db_url = format!(
         "postgres://{}:{}@{}:{}/{}",
@@ -16,6 +41,11 @@ pub fn pool(config: &PostgresConfig) -> Result<PgPool, Error> {
     Ok(Pool::builder()
         .min_idle(Some(2))
         .max_size(config.pool_size as u32)
-        .idle_timeout(Some(Duration::from_secs(5 * 60)))
+        .max_lifetime(Some(config.max_lifetime))
+        .idle_timeout(Some(config.idle_timeout))
+        .connection_timeout(config.connection_timeout)
+        .connection_customizer(Box::new(SessionSettings {
+            application_name: application_name(&config.instance),
+        }))
         .build(manager)?)
 }
@@ -3,8 +3,11 @@ use std::fmt::Display;
 #[derive(Debug)]
 pub enum Error {
     LoadConfigFailed(envy::Error),
+    InvalidConfig(String),
     InvalidMessage(String),
     DbError(diesel::result::Error),
+    SchemaMismatch(String),
+    CircuitOpen(std::time::Duration),
     ConnectionPoolError(r2d2::Error),
     OpenTelemetryTraceError(opentelemetry::trace::TraceError),
     TracingSubscriberTryInitError(tracing_subscriber::util::TryInitError),
@@ -53,8 +56,13 @@ impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             LoadConfigFailed(err) => write!(f, "LoadConfigFailed: {}", err),
+            InvalidConfig(reason) => write!(f, "InvalidConfig: {}", reason),
             InvalidMessage(message) => write!(f, "InvalidMessage: {}", message),
             DbError(err) => write!(f, "DbError: {}", err),
+            SchemaMismatch(details) => write!(f, "SchemaMismatch: {}", details),
+            CircuitOpen(retry_after) => {
+                write!(f, "CircuitOpen: retry after {}s", retry_after.as_secs())
+            }
             ConnectionPoolError(err) => write!(f, "ConnectionPoolError: {}", err),
             OpenTelemetryTraceError(err) => write!(f, "OpenTelemetryTraceError: {}", err),
             TracingSubscriberTryInitError(err) => {
@@ -2,6 +2,7 @@
 extern crate diesel;
 
 pub mod api;
+pub mod circuit_breaker;
 pub mod config;
 pub mod data_entries;
 pub mod db;
@@ -11,8 +12,11 @@ pub mod text_utils;
 
 // tracing
 use opentelemetry::global;
+use opentelemetry::sdk::trace::{self as sdktrace, Sampler};
 use tracing_subscriber::prelude::*;
 
+const SCHEMA_MISMATCH_EXIT_CODE: i32 = 3;
+
 #[tokio::main]
 async fn main() -> Result<(), error::Error> {
     let config = config::load()?;
@@ -20,8 +24,8 @@ async fn main() -> Result<(), error::Error> {
     let mut tracing_enabled = false;
 
     if let (Some(service_name_prefix), Some(jaeger_agent_endpoint)) = (
-        config.tracing.service_name_prefix,
-        config.tracing.jaeger_agent_endpoint,
+        config.tracing.service_name_prefix.clone(),
+        config.tracing.jaeger_agent_endpoint.clone(),
     ) {
         tracing_enabled = true;
         println!(
@@ -33,6 +37,7 @@ async fn main() -> Result<(), error::Error> {
         let tracer = opentelemetry_jaeger::new_pipeline()
             .with_service_name(format!("{}/state-service", service_name_prefix))
             .with_agent_endpoint(jaeger_agent_endpoint)
+            .with_trace_config(sdktrace::config().with_sampler(sampler(config.tracing.sample_rate)))
             .install_batch(opentelemetry::runtime::Tokio)?;
 
         let opentelemetry = tracing_opentelemetry::layer().with_tracer(tracer);
@@ -45,10 +50,34 @@ async fn main() -> Result<(), error::Error> {
 
     let data_entries_repo = {
         let pg_pool = db::pool(&config.postgres)?;
-        data_entries::Repo::new(pg_pool)
+        let breaker = circuit_breaker::CircuitBreaker::new(
+            config.db_breaker_failures,
+            config.db_breaker_cooldown,
+        );
+        let sql = api::SqlContext {
+            md5_min_value_len: config.value_md5_min_len,
+        };
+        data_entries::Repo::new(pg_pool, config.postgres.schema.clone(), breaker, sql)
     };
 
-    api::start(config.port, config.metrics_port, data_entries_repo).await;
+    match data_entries_repo.check_schema().await {
+        Err(err @ error::Error::SchemaMismatch(_)) => {
+            eprintln!("{}", err);
+            std::process::exit(SCHEMA_MISMATCH_EXIT_CODE);
+        }
+        res => res?,
+    }
+
+    // a zero rate would never refill, it disables the limiter like a missing one
+    let rate_limiter = config
+        .rate_limit_per_sec
+        .filter(|rate| *rate > 0)
+        .map(|rate| {
+            api::rate_limit::RateLimiter::new(rate, config.rate_limit_burst.unwrap_or(rate))
+        });
+
+
+    api::start(&config, rate_limiter, data_entries_repo).await;
 
     if tracing_enabled {
         global::shutdown_tracer_provider();
@@ -56,3 +85,27 @@ async fn main() -> Result<(), error::Error> {
 
     Ok(())
 }
+
+// traces continued from a caller keep its sampling decision
+fn sampler(sample_rate: Option<f64>) -> Sampler {
+    match sample_rate {
+        Some(rate) => Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(rate))),
+        None => Sampler::AlwaysOn,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_by_rate_unless_parent_decided() {
+        assert!(matches!(sampler(None), Sampler::AlwaysOn));
+        match sampler(Some(0.25)) {
+            Sampler::ParentBased(root) => {
+                assert!(matches!(*root, Sampler::TraceIdRatioBased(rate) if rate == 0.25))
+            }
+            sampler => panic!("unexpected sampler {:?}", sampler),
+        }
+    }
+}
@@ -1,46 +1,38 @@
 use std::borrow::Cow;
 
-pub fn pg_escape<'a>(text: &'a str) -> Cow<'a, str> {
-	let bytes = text.as_bytes();
+/// Escapes text for a single-quoted sql literal by doubling the quotes.
+/// Backslashes are ordinary chars there, since the pool turns `standard_conforming_strings`
+/// on for every connection.
+pub fn pg_escape(text: &str) -> Cow<'_, str> {
+    if text.contains('\'') {
+        Cow::Owned(text.replace('\'', "''"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-	let mut owned = None;
+    #[test]
+    fn doubles_quotes_only() {
+        assert_eq!(pg_escape("plain"), "plain");
+        assert_eq!(pg_escape("it's"), "it''s");
+        assert_eq!(pg_escape(r#"{"a b": "c\"d"}"#), r#"{"a b": "c\"d"}"#);
+        assert_eq!(pg_escape(r"C:\path"), r"C:\path");
+    }
 
-	for pos in 0..bytes.len() {
-		let special =
-			match bytes[pos] {
-				0x07 => Some(b'a'),
-				0x08 => Some(b'b'),
-				b'\t' => Some(b't'),
-				b'\n' => Some(b'n'),
-				0x0b => Some(b'v'),
-				0x0c => Some(b'f'),
-				b'\r' => Some(b'r'),
-				b' ' => Some(b' '),
-				b'\\' => Some(b'\\'),
-				b'\'' => Some(b'\''),
-				_ => None,
-			};
-		
-      if let Some(s) = special {
-        if owned.is_none() {
-        	owned = Some(bytes[0..pos].to_owned());
-        }
-        if s.eq(&b'\'') {
-          owned.as_mut().unwrap().push(b'\'');
-        } else {
-          owned.as_mut().unwrap().push(b'\\');
-        }
-        owned.as_mut().unwrap().push(s);
-  		}
-		else if let Some(owned) = owned.as_mut() {
-			owned.push( bytes[pos] );
-		}
-	}
+    #[test]
+    fn backslash_before_quote_stays_in_literal() {
+        // were backslash escapes on, `\'` would be a quote and the next one would end the literal
+        let literal = format!("'{}'", pg_escape(r"a\' OR true --"));
 
-	if let Some(owned) = owned {
-		unsafe { Cow::Owned(String::from_utf8_unchecked(owned)) }
-	}
-	else {
-		unsafe { Cow::Borrowed(std::str::from_utf8_unchecked(bytes)) }
-	}
+        assert_eq!(literal, r"'a\'' OR true --'");
+        // the only quotes left undoubled are the delimiters
+        assert_eq!(
+            literal[1..literal.len() - 1].replace("''", ""),
+            r"a\ OR true --"
+        );
+    }
 }